@@ -0,0 +1,336 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Chain export.
+//! Streams a range of stored blocks into a portable archive, for off-site
+//! archival of a chain. The archive consists of:
+//!     [magic "CITABLKS" (8 bytes)]
+//!     [format version (1 byte)]
+//!     [flags (1 byte), bit 0 is set when receipts are included]
+//!     [records, each a length (4 bytes big-endian) followed by RLP]
+//! A record is the RLP list `[header, body]`, or `[header, body, receipts]`
+//! when receipts are included, in ascending height order.
+
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+use byteorder::{BigEndian, ByteOrder};
+use cita_types::H256;
+use header::{BlockNumber, Header};
+use libchain::chain::Chain;
+use receipt::Receipt;
+use rlp::{RlpStream, UntrustedRlp};
+use types::block::BlockBody;
+use types::ids::BlockId;
+
+pub const EXPORT_MAGIC: &[u8; 8] = b"CITABLKS";
+pub const EXPORT_VERSION: u8 = 1;
+/// Largest record accepted, well above any block within the quota limits.
+pub const MAX_EXPORT_RECORD_LEN: usize = 64 * 1024 * 1024;
+
+const FLAG_RECEIPTS: u8 = 0b1;
+
+/// A block read back from an archive.
+#[derive(Debug)]
+pub struct ExportedBlock {
+    pub header: Header,
+    pub body: BlockBody,
+    pub receipts: Option<Vec<Receipt>>,
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Write the archive preamble.
+pub fn write_export_header<W: Write>(writer: &mut W, with_receipts: bool) -> io::Result<()> {
+    let flags = if with_receipts { FLAG_RECEIPTS } else { 0 };
+    writer.write_all(EXPORT_MAGIC)?;
+    writer.write_all(&[EXPORT_VERSION, flags])
+}
+
+/// Write one block record.
+pub fn write_export_block<W: Write>(
+    writer: &mut W,
+    header: &Header,
+    body: &BlockBody,
+    receipts: Option<&[Receipt]>,
+) -> io::Result<()> {
+    let mut stream = RlpStream::new_list(if receipts.is_some() { 3 } else { 2 });
+    stream.append(header).append(body);
+    if let Some(receipts) = receipts {
+        stream.append_list(receipts);
+    }
+    let record = stream.out();
+    if record.len() > MAX_EXPORT_RECORD_LEN {
+        return Err(invalid_data(format!(
+            "block {} record of {} bytes is too large",
+            header.number(),
+            record.len()
+        )));
+    }
+
+    let mut len = [0u8; 4];
+    BigEndian::write_u32(&mut len, record.len() as u32);
+    writer.write_all(&len)?;
+    writer.write_all(&record)
+}
+
+/// Read the archive preamble, returning whether records carry receipts.
+pub fn read_export_header<R: Read>(reader: &mut R) -> io::Result<bool> {
+    let mut preamble = [0u8; 10];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..8] != EXPORT_MAGIC {
+        return Err(invalid_data("not a chain export archive".to_owned()));
+    }
+    if preamble[8] != EXPORT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported export version {}",
+            preamble[8]
+        )));
+    }
+    Ok(preamble[9] & FLAG_RECEIPTS != 0)
+}
+
+/// Read the next block record, or `None` at the end of the archive.
+pub fn read_export_block<R: Read>(
+    reader: &mut R,
+    with_receipts: bool,
+) -> io::Result<Option<ExportedBlock>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let len = BigEndian::read_u32(&len) as usize;
+    if len > MAX_EXPORT_RECORD_LEN {
+        return Err(invalid_data(format!(
+            "record of {} bytes is too large",
+            len
+        )));
+    }
+    let mut record = vec![0u8; len];
+    reader.read_exact(&mut record)?;
+
+    let rlp = UntrustedRlp::new(&record);
+    let decode = || -> Result<ExportedBlock, ::rlp::DecoderError> {
+        Ok(ExportedBlock {
+            header: rlp.val_at(0)?,
+            body: rlp.val_at(1)?,
+            receipts: if with_receipts {
+                Some(rlp.list_at(2)?)
+            } else {
+                None
+            },
+        })
+    };
+    decode()
+        .map(Some)
+        .map_err(|err| invalid_data(format!("bad block record: {:?}", err)))
+}
+
+/// Read back a whole archive, checking that its blocks are consecutive and
+/// linked by parent hash, and that a block with receipts has one for each
+/// transaction. Returns the heights the archive holds.
+pub fn verify_export<R: Read>(reader: &mut R) -> io::Result<Range<BlockNumber>> {
+    let with_receipts = read_export_header(reader)?;
+    let mut heights: Option<Range<BlockNumber>> = None;
+    let mut parent_hash: Option<H256> = None;
+    while let Some(block) = read_export_block(reader, with_receipts)? {
+        let number = block.header.number();
+        if let Some(ref heights) = heights {
+            if number != heights.end {
+                return Err(invalid_data(format!(
+                    "block {} follows block {}",
+                    number,
+                    heights.end - 1
+                )));
+            }
+        }
+        if let Some(parent_hash) = parent_hash {
+            if *block.header.parent_hash() != parent_hash {
+                return Err(invalid_data(format!(
+                    "block {} does not link to its parent",
+                    number
+                )));
+            }
+        }
+        if let Some(ref receipts) = block.receipts {
+            if receipts.len() != block.body.transactions().len() {
+                return Err(invalid_data(format!(
+                    "block {} has {} receipts for {} transactions",
+                    number,
+                    receipts.len(),
+                    block.body.transactions().len()
+                )));
+            }
+        }
+        parent_hash = block.header.hash();
+        heights = Some(heights.map_or(number, |heights| heights.start)..number + 1);
+    }
+    Ok(heights.unwrap_or(0..0))
+}
+
+impl Chain {
+    /// Export blocks in `range` to `writer`, returning the number of blocks
+    /// written. Fails if any block in the range is not stored.
+    pub fn export_chain<W: Write>(
+        &self,
+        range: Range<BlockNumber>,
+        with_receipts: bool,
+        writer: &mut W,
+    ) -> io::Result<u64> {
+        write_export_header(writer, with_receipts)?;
+        let mut exported = 0;
+        for height in range {
+            let missing = || {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("block {} is not stored", height),
+                )
+            };
+            let header = self
+                .block_header(BlockId::Number(height))
+                .ok_or_else(missing)?;
+            let body = self
+                .block_body(BlockId::Number(height))
+                .ok_or_else(missing)?;
+            let receipts = if with_receipts {
+                // Blocks without transactions may have no receipts stored.
+                match self.block_receipts(header.hash().unwrap()) {
+                    Some(receipts) => Some(receipts.receipts),
+                    None if body.transactions().is_empty() => Some(Vec::new()),
+                    None => return Err(missing()),
+                }
+            } else {
+                None
+            };
+            write_export_block(writer, &header, &body, receipts.as_ref().map(|r| &r[..]))?;
+            exported += 1;
+        }
+        writer.flush()?;
+        Ok(exported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cita_types::U256;
+
+    fn header_at(number: BlockNumber) -> Header {
+        let mut header = Header::default();
+        header.set_number(number);
+        header.set_timestamp(number * 3000);
+        header
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let receipt = Receipt::new(
+            None,
+            U256::from(21000),
+            vec![],
+            None,
+            U256::zero(),
+            Default::default(),
+        );
+
+        let mut archive = Vec::new();
+        write_export_header(&mut archive, true).unwrap();
+        write_export_block(
+            &mut archive,
+            &header_at(1),
+            &BlockBody::default(),
+            Some(&[]),
+        )
+        .unwrap();
+        write_export_block(
+            &mut archive,
+            &header_at(2),
+            &BlockBody::default(),
+            Some(&[receipt.clone()]),
+        )
+        .unwrap();
+
+        let mut reader = &archive[..];
+        assert!(read_export_header(&mut reader).unwrap());
+        let first = read_export_block(&mut reader, true).unwrap().unwrap();
+        assert_eq!(first.header, header_at(1));
+        assert_eq!(first.receipts, Some(vec![]));
+        let second = read_export_block(&mut reader, true).unwrap().unwrap();
+        assert_eq!(second.header, header_at(2));
+        assert_eq!(second.body, BlockBody::default());
+        assert_eq!(second.receipts, Some(vec![receipt]));
+        assert!(read_export_block(&mut reader, true).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_export_header_checks() {
+        let mut archive = Vec::new();
+        write_export_header(&mut archive, false).unwrap();
+        assert!(!read_export_header(&mut &archive[..]).unwrap());
+
+        archive[8] = EXPORT_VERSION + 1;
+        assert!(read_export_header(&mut &archive[..]).is_err());
+
+        archive[0] = b'X';
+        assert!(read_export_header(&mut &archive[..]).is_err());
+    }
+
+    #[test]
+    fn test_verify_export() {
+        let mut first = header_at(1);
+        first.rehash();
+        let mut second = header_at(2);
+        second.set_parent_hash(first.hash().unwrap());
+
+        let mut archive = Vec::new();
+        write_export_header(&mut archive, false).unwrap();
+        write_export_block(&mut archive, &first, &BlockBody::default(), None).unwrap();
+        write_export_block(&mut archive, &second, &BlockBody::default(), None).unwrap();
+        assert_eq!(verify_export(&mut &archive[..]).unwrap(), 1..3);
+
+        // A block which does not link to its parent.
+        let mut archive = Vec::new();
+        write_export_header(&mut archive, false).unwrap();
+        write_export_block(&mut archive, &first, &BlockBody::default(), None).unwrap();
+        write_export_block(&mut archive, &header_at(2), &BlockBody::default(), None).unwrap();
+        let err = verify_export(&mut &archive[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A gap in the heights.
+        let mut archive = Vec::new();
+        write_export_header(&mut archive, false).unwrap();
+        write_export_block(&mut archive, &first, &BlockBody::default(), None).unwrap();
+        write_export_block(&mut archive, &header_at(3), &BlockBody::default(), None).unwrap();
+        assert!(verify_export(&mut &archive[..]).is_err());
+    }
+
+    #[test]
+    fn test_export_record_too_large() {
+        let mut archive = Vec::new();
+        write_export_header(&mut archive, false).unwrap();
+        archive.extend_from_slice(&[0xff; 4]);
+
+        let mut reader = &archive[..];
+        read_export_header(&mut reader).unwrap();
+        let err = read_export_block(&mut reader, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -17,6 +17,7 @@
 
 pub mod cache;
 pub mod chain;
+pub mod export;
 pub mod rich_status;
 pub mod status;
 pub use cita_db::journaldb;
@@ -98,6 +98,11 @@ use core::libchain;
 use forward::Forward;
 use libproto::router::{MsgType, RoutingKey, SubModules};
 use pubsub::start_pubsub;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::ops::Range;
+use std::path::Path;
+use std::process;
 use std::sync::mpsc::channel;
 use std::sync::Arc;
 use std::thread;
@@ -107,6 +112,52 @@ use util::set_panic_handler;
 
 include!(concat!(env!("OUT_DIR"), "/build_info.rs"));
 
+fn parse_heights(heights: &str) -> Option<Range<u64>> {
+    let mut bounds = heights.splitn(2, "..");
+    let from = bounds.next()?.trim().parse().ok()?;
+    let to = bounds.next()?.trim().parse().ok()?;
+    Some(from..to)
+}
+
+// Open the chain database as it is, without joining the network.
+fn export_chain(config_path: &str, heights: Range<u64>, path: &str, with_receipts: bool) {
+    let nosql_path = DataPath::nosql_path();
+    if !Path::new(&nosql_path).is_dir() {
+        eprintln!("No chain database found in {}.", nosql_path);
+        process::exit(1);
+    }
+    let db_config = DatabaseConfig::with_columns(db::NUM_COLUMNS);
+    let db = Database::open(&db_config, &nosql_path).unwrap();
+    let chain_config = libchain::chain::Config::new(config_path);
+    let chain = libchain::chain::Chain::init_chain(Arc::new(db), &chain_config);
+
+    let (from, to) = (heights.start, heights.end);
+    let exported = File::create(path)
+        .and_then(|file| chain.export_chain(heights, with_receipts, &mut BufWriter::new(file)));
+    match exported {
+        Ok(count) => println!("Exported {} blocks {}..{} to {}.", count, from, to, path),
+        Err(err) => {
+            eprintln!("Failed to export blocks {}..{}: {}.", from, to, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn verify_export(path: &str) {
+    let verified = File::open(path)
+        .and_then(|file| libchain::export::verify_export(&mut BufReader::new(file)));
+    match verified {
+        Ok(heights) => println!(
+            "Export file {} holds blocks {}..{}.",
+            path, heights.start, heights.end
+        ),
+        Err(err) => {
+            eprintln!("Export file {} is invalid: {}.", path, err);
+            process::exit(1);
+        }
+    }
+}
+
 fn main() {
     micro_service_init!("cita-chain", "CITA:chain");
     info!("Version: {}", get_build_info_str(true));
@@ -117,10 +168,42 @@ fn main() {
         .author("Cryptape")
         .about("CITA Block Chain Node powered by Rust")
         .arg_from_usage("-c, --config=[FILE] 'Sets a chain config file'")
+        .arg_from_usage(
+            "--export=[HEIGHTS] 'Exports blocks FROM..TO (TO excluded) to the export file \
+             and exits'",
+        )
+        .arg_from_usage("--export-file=[FILE] 'Sets the export file, chain_export.bin by default'")
+        .arg_from_usage("--with-receipts 'Exports the receipts of the blocks too'")
+        .arg_from_usage(
+            "--verify-export=[FILE] 'Reads back an export file, checks its blocks and exits'",
+        )
         .get_matches();
 
     let config_path = matches.value_of("config").unwrap_or("chain.toml");
 
+    if let Some(path) = matches.value_of("verify-export") {
+        verify_export(path);
+        return;
+    }
+
+    // Run it on a stopped node.
+    if let Some(heights) = matches.value_of("export") {
+        let heights = parse_heights(heights).unwrap_or_else(|| {
+            eprintln!("Invalid heights {}, expected FROM..TO.", heights);
+            process::exit(1);
+        });
+        let path = matches
+            .value_of("export-file")
+            .unwrap_or("chain_export.bin");
+        export_chain(
+            config_path,
+            heights,
+            path,
+            matches.is_present("with-receipts"),
+        );
+        return;
+    }
+
     let (tx, rx) = channel();
     let (ctx_pub, crx_pub) = channel();
     start_pubsub(
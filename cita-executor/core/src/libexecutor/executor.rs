@@ -34,7 +34,7 @@ use evm::Factory as EvmFactory;
 use factory::*;
 use header::*;
 pub use libexecutor::block::*;
-use libexecutor::genesis::Genesis;
+use libexecutor::genesis::{Genesis, SpecError};
 use libproto::{ConsensusConfig, ExecutedResult};
 use state_db::StateDB;
 use std::convert::{From, Into};
@@ -74,8 +74,8 @@ impl Executor {
         command_req_receiver: Receiver<Command>,
         command_resp_sender: Sender<CommandResp>,
         eth_compatibility: bool,
-    ) -> Executor {
-        let mut genesis = Genesis::init(&genesis_path);
+    ) -> Result<Executor, SpecError> {
+        let mut genesis = Genesis::init(&genesis_path)?;
         let database = open_state_db(data_path);
         let database: Arc<KeyValueDB> = Arc::new(database);
         let journaldb_type = journaldb_type
@@ -94,9 +94,7 @@ impl Executor {
             Some(header) => header,
             None => {
                 warn!("Not found exist block within database. Loading genesis block...");
-                genesis
-                    .lazy_execute(&state_db, &factories)
-                    .expect("failed to load genesis");
+                genesis.lazy_execute(&state_db, &factories)?;
                 genesis.block.header().clone()
             }
         };
@@ -121,7 +119,7 @@ impl Executor {
             executor.get_current_height(),
             executor.get_current_hash(),
         );
-        executor
+        Ok(executor)
     }
 
     pub fn close(&mut self) {
@@ -425,7 +423,7 @@ pub fn get_current_header(db: &KeyValueDB) -> Option<Header> {
     }
 }
 
-fn open_state_db(data_path: String) -> Database {
+pub fn open_state_db(data_path: String) -> Database {
     let database_config = DatabaseConfig::with_columns(db::NUM_COLUMNS);
     let nosql_path = data_path + "/statedb";
     Database::open(&database_config, &nosql_path).unwrap()
@@ -472,11 +470,13 @@ mod tests {
     use cita_types::traits::LowerHex;
     use cita_types::Address;
     use contracts::solc::sys_config::SysConfig;
+    use core::libchain::export::{read_export_block, read_export_header};
     use core::receipt::ReceiptError;
     use libexecutor::command::Commander;
     use libexecutor::command::{Command, CommandResp};
     use libexecutor::fsm::FSM;
     use rustc_hex::FromHex;
+    use std::io;
     use std::str::FromStr;
     use std::thread;
     use std::time::Duration;
@@ -512,6 +512,44 @@ mod tests {
         assert_eq!(receipt.error, Some(ReceiptError::NoContractPermission));
     }
 
+    #[test]
+    fn test_export_chain() {
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+        let mut executor = helpers::init_executor(vec![]);
+        let chain = helpers::init_chain();
+        let data = helpers::generate_contract();
+
+        // A block with a transaction, then an empty one without receipts.
+        for nonce in &[(0, 1), (1, 1)] {
+            let block = helpers::create_block(&executor, Address::from(0), &data, *nonce, &privkey);
+            let h = executor.get_current_height() + 1;
+            let closed_block = executor.into_fsm(block.clone());
+            let executed_result = executor.grow(closed_block);
+            chain.set_block_body(h, &block);
+            chain.set_db_result(&executed_result, &block);
+        }
+
+        let mut archive = Vec::new();
+        assert_eq!(chain.export_chain(1..3, true, &mut archive).unwrap(), 2);
+        let mut reader = &archive[..];
+        assert!(read_export_header(&mut reader).unwrap());
+        let first = read_export_block(&mut reader, true).unwrap().unwrap();
+        assert_eq!(first.header.number(), 1);
+        assert_eq!(first.body.transactions().len(), 1);
+        assert_eq!(first.receipts.unwrap().len(), 1);
+        let second = read_export_block(&mut reader, true).unwrap().unwrap();
+        assert_eq!(second.header.number(), 2);
+        assert!(second.body.transactions().is_empty());
+        assert_eq!(second.receipts, Some(vec![]));
+        assert!(read_export_block(&mut reader, true).unwrap().is_none());
+
+        let err = chain
+            .export_chain(1..4, false, &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
     #[test]
     fn test_chain_name_valid_block_number() {
         let keypair = KeyPair::gen_keypair();
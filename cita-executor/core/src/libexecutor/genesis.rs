@@ -16,28 +16,33 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use cita_db::kvdb::KeyValueDB;
-use cita_types::traits::ConvertType;
+use cita_db::HashDB;
+use cita_types::traits::{ConvertType, LowerHex};
 use cita_types::{clean_0x, Address, H256, U256};
 use crypto::digest::Digest;
 use crypto::md5::Md5;
 use db::{self as db, Writable};
 use factory::Factories;
+use hashable::Hashable;
 use libexecutor::block::Block;
-use rustc_hex::FromHex;
-use serde_json;
+use pod_account::PodAccount;
+use rustc_hex::{FromHex, ToHex};
+use serde_json::{self, Value};
+use snapshot::{self, iterate_state};
 use state::State;
 use state_db::StateDB;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::BufReader;
-use std::io::Read;
-use std::path::Path;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use types::extras::*;
+use util::Mismatch;
 #[cfg(feature = "privatetx")]
 use zktx::set_param_path;
 
-#[derive(Debug, PartialEq, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Contract {
     pub nonce: String,
     pub code: String,
@@ -45,77 +50,369 @@ pub struct Contract {
     pub value: Option<U256>,
 }
 
+impl Contract {
+    /// Express `account` as an `alloc` entry. Its abi is dropped, and its
+    /// nonce is kept but not applied by `lazy_execute`.
+    pub fn from_pod(account: &PodAccount) -> Contract {
+        Contract {
+            nonce: account.nonce.to_string(),
+            code: format!(
+                "0x{}",
+                account
+                    .code
+                    .as_ref()
+                    .map_or_else(String::new, |code| code.to_hex())
+            ),
+            storage: account
+                .storage
+                .iter()
+                .map(|(key, value)| {
+                    (
+                        format!("0x{}", key.lower_hex()),
+                        format!("0x{}", value.lower_hex()),
+                    )
+                })
+                .collect(),
+            value: if account.balance.is_zero() {
+                None
+            } else {
+                Some(account.balance)
+            },
+        }
+    }
+}
+
+/// Dump the state under `root` as an `alloc`, to launch a new chain from it.
+pub fn alloc_from_state(
+    db: &HashDB,
+    root: &H256,
+) -> Result<HashMap<String, Contract>, snapshot::Error> {
+    let mut alloc = HashMap::new();
+    iterate_state(db, root, |address, account| {
+        alloc.insert(
+            format!("0x{}", address.lower_hex()),
+            Contract::from_pod(&account),
+        );
+        Ok(())
+    })?;
+    Ok(alloc)
+}
+
 #[derive(Debug, PartialEq, Deserialize)]
 pub struct Spec {
     pub alloc: HashMap<String, Contract>,
     pub prevhash: H256,
     pub timestamp: u64,
+    /// Expected state root after initializing `alloc`, verified if given.
+    #[serde(default)]
+    pub state_root: Option<H256>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Genesis {
     pub spec: Spec,
+    /// Hash of the normalized genesis spec, identical on every node running
+    /// the same parameters.
+    pub spec_hash: H256,
     pub block: Block,
 }
 
+/// Errors of loading a genesis spec.
+#[derive(Debug)]
+pub enum SpecError {
+    /// A genesis or resource file could not be read.
+    Io(PathBuf, io::Error),
+    /// A genesis file is not valid json.
+    Json(PathBuf, serde_json::Error),
+    /// The resolved genesis spec does not match the `Spec` structure.
+    InvalidSpec(serde_json::Error),
+    /// A genesis file is its own base, directly or not.
+    CyclicBase(PathBuf),
+    /// The `base` of a genesis file is not a path.
+    InvalidBase(PathBuf, String),
+    /// The `spec_version` is invalid or not supported.
+    InvalidVersion(String),
+    /// The resource hash does not match `prevhash`.
+    InvalidPrevHash(Mismatch<H256>),
+    /// The resource hash is not a valid hash.
+    InvalidResourceHash(String),
+    /// An `alloc` entry has an invalid address, code or storage.
+    InvalidAlloc(String),
+    /// Initializing the genesis state failed.
+    State(String),
+    /// Writing the genesis block failed.
+    Database(String),
+    /// The genesis state root does not match `state_root`.
+    InvalidStateRoot(Mismatch<H256>),
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpecError::Io(ref path, ref err) => write!(f, "failed to read {:?}: {}", path, err),
+            SpecError::Json(ref path, ref err) => write!(f, "invalid json in {:?}: {}", path, err),
+            SpecError::InvalidSpec(ref err) => write!(f, "invalid genesis spec: {}", err),
+            SpecError::CyclicBase(ref path) => write!(f, "cyclic base reference in {:?}", path),
+            SpecError::InvalidBase(ref path, ref base) => {
+                write!(f, "invalid base {} in {:?}", base, path)
+            }
+            SpecError::InvalidVersion(ref msg) => f.write_str(msg),
+            SpecError::InvalidPrevHash(ref mis) => write!(
+                f,
+                "resource hash mismatch: prevhash {:?}, resource hash {:?}",
+                mis.expected, mis.found
+            ),
+            SpecError::InvalidResourceHash(ref hash) => write!(f, "invalid resource hash {}", hash),
+            SpecError::InvalidAlloc(ref msg) => write!(f, "invalid alloc: {}", msg),
+            SpecError::State(ref msg) => write!(f, "genesis state error: {}", msg),
+            SpecError::Database(ref msg) => write!(f, "genesis database error: {}", msg),
+            SpecError::InvalidStateRoot(ref mis) => write!(
+                f,
+                "genesis state root mismatch: expected {:?}, got {:?}",
+                mis.expected, mis.found
+            ),
+        }
+    }
+}
+
+/// Key of the genesis field which references a base genesis file.
+const BASE_KEY: &str = "base";
+/// Key of the genesis field which declares the spec version.
+const SPEC_VERSION_KEY: &str = "spec_version";
+/// Spec version of genesis files written before `spec_version` existed.
+const LEGACY_SPEC_VERSION: (u64, u64) = (1, 0);
+/// Spec version supported by this executor.
+const SPEC_VERSION: (u64, u64) = (1, 0);
+
+/// Parse a `major.minor` spec version.
+fn parse_spec_version(version: &str) -> Option<(u64, u64)> {
+    let mut parts = version.split('.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(major), Some(minor), None) => match (major.parse(), minor.parse()) {
+            (Ok(major), Ok(minor)) => Some((major, minor)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Check the `spec_version` of a resolved genesis spec and normalize it.
+///
+/// Specs of a newer major version are refused. Genesis files without a
+/// `spec_version` are still accepted as the legacy version, with a warning,
+/// and the version is written into the spec so that both forms hash alike.
+fn normalize_spec_version(value: &mut Value) -> Result<(), String> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| "genesis spec is not a json object".to_owned())?;
+    let version = match object.get(SPEC_VERSION_KEY) {
+        Some(&Value::String(ref version)) => parse_spec_version(version)
+            .ok_or_else(|| format!("invalid spec_version {}", version))?,
+        Some(version) => return Err(format!("invalid spec_version {}", version)),
+        None => {
+            warn!(
+                "genesis spec has no spec_version, assuming {}.{}",
+                LEGACY_SPEC_VERSION.0, LEGACY_SPEC_VERSION.1
+            );
+            LEGACY_SPEC_VERSION
+        }
+    };
+    if version.0 > SPEC_VERSION.0 {
+        return Err(format!(
+            "spec_version {}.{} is not supported, the newest supported is {}.{}",
+            version.0, version.1, SPEC_VERSION.0, SPEC_VERSION.1
+        ));
+    }
+    object.insert(
+        SPEC_VERSION_KEY.to_owned(),
+        Value::String(format!("{}.{}", version.0, version.1)),
+    );
+    Ok(())
+}
+
+/// Load the genesis file at `path` as raw json, resolving its `base` chain.
+///
+/// The `base` path is relative to the directory of the file declaring it.
+/// Fields of the current file override the ones of its base, objects are
+/// merged recursively and a `null` value removes the field from the base.
+fn load_spec_value(path: &Path, visited: &mut Vec<PathBuf>) -> Result<Value, SpecError> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|err| SpecError::Io(path.to_owned(), err))?;
+    if visited.contains(&canonical) {
+        return Err(SpecError::CyclicBase(path.to_owned()));
+    }
+    visited.push(canonical);
+
+    let config_file = File::open(path).map_err(|err| SpecError::Io(path.to_owned(), err))?;
+    let fconfig = BufReader::new(config_file);
+    let mut value: Value =
+        serde_json::from_reader(fconfig).map_err(|err| SpecError::Json(path.to_owned(), err))?;
+
+    let base = value
+        .as_object_mut()
+        .and_then(|object| object.remove(BASE_KEY));
+    match base {
+        Some(Value::String(base)) => {
+            let base_path = spec_dir(path).join(base);
+            let mut base_value = load_spec_value(&base_path, visited)?;
+            merge_spec_value(&mut base_value, value);
+            Ok(base_value)
+        }
+        Some(Value::Null) | None => Ok(value),
+        Some(base) => Err(SpecError::InvalidBase(path.to_owned(), base.to_string())),
+    }
+}
+
+/// Directory of a genesis file, where its base and resources are looked up.
+fn spec_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+/// Hash the resource files listed in `resource/files.list`, if any.
+fn resource_hash(resource_path: &Path) -> Result<Option<H256>, SpecError> {
+    let read_file = |path: &Path, buf: &mut Vec<u8>| {
+        File::open(path)
+            .and_then(|file| BufReader::new(file).read_to_end(buf))
+            .map_err(|err| SpecError::Io(path.to_owned(), err))
+    };
+
+    let file_list_path = resource_path.join("files.list");
+    if !file_list_path.exists() {
+        return Ok(None);
+    }
+    let mut contents = Vec::new();
+    read_file(&file_list_path, &mut contents)?;
+    let mut hasher = Md5::new();
+    for p in String::from_utf8_lossy(&contents).lines() {
+        let mut buf = Vec::new();
+        read_file(&resource_path.join(p), &mut buf)?;
+        hasher.input(&buf);
+    }
+    let mut hash_str = "0x00000000000000000000000000000000".to_string();
+    hash_str += &hasher.result_str();
+    info!("resource hash {}", hash_str);
+    H256::from_unaligned(hash_str.as_str())
+        .map(Some)
+        .map_err(|_| SpecError::InvalidResourceHash(hash_str.clone()))
+}
+
+fn state_error<E: fmt::Display>(err: E) -> SpecError {
+    SpecError::State(err.to_string())
+}
+
+/// An `alloc` entry with its fields parsed.
+struct GenesisAccount {
+    address: Address,
+    code: Vec<u8>,
+    value: Option<U256>,
+    storage: Vec<(H256, H256)>,
+}
+
+/// Parse the `alloc` entries of a genesis spec.
+fn parse_alloc(alloc: &HashMap<String, Contract>) -> Result<Vec<GenesisAccount>, SpecError> {
+    alloc
+        .iter()
+        .map(|(address, contract)| {
+            let invalid =
+                |what: String| SpecError::InvalidAlloc(format!("{} of {}", what, address));
+            let parsed_address = Address::from_unaligned(address.as_str())
+                .map_err(|_| invalid("address".to_owned()))?;
+            let code = clean_0x(&contract.code)
+                .from_hex()
+                .map_err(|_| invalid(format!("code {}", contract.code)))?;
+            let storage = contract
+                .storage
+                .iter()
+                .map(|(key, value)| {
+                    match (
+                        H256::from_unaligned(key.as_str()),
+                        H256::from_unaligned(value.as_str()),
+                    ) {
+                        (Ok(key), Ok(value)) => Ok((key, value)),
+                        _ => Err(invalid(format!("storage {}: {}", key, value))),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(GenesisAccount {
+                address: parsed_address,
+                code,
+                value: contract.value,
+                storage,
+            })
+        })
+        .collect()
+}
+
+/// Merge `overlay` into `base`, the fields of `overlay` take precedence.
+fn merge_spec_value(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (&mut Value::Object(ref mut base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                if value.is_null() {
+                    base.remove(&key);
+                } else if base.contains_key(&key) {
+                    merge_spec_value(base.get_mut(&key).unwrap(), value);
+                } else {
+                    base.insert(key, value);
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
 impl Genesis {
-    pub fn init(path: &str) -> Genesis {
-        let config_file = File::open(path).unwrap();
-        let fconfig = BufReader::new(config_file);
-        let spec: Spec = serde_json::from_reader(fconfig).expect("Failed to load genesis.");
+    pub fn init(path: &str) -> Result<Genesis, SpecError> {
+        let mut value = load_spec_value(Path::new(path), &mut Vec::new())?;
+        normalize_spec_version(&mut value).map_err(SpecError::InvalidVersion)?;
+        let spec_hash = value.to_string().into_bytes().crypt_hash();
+        info!("genesis spec hash {:?}", spec_hash);
+        let spec: Spec = serde_json::from_value(value).map_err(SpecError::InvalidSpec)?;
+        parse_alloc(&spec.alloc)?;
 
         // check resource with pre hash in genesis
         // default pre hash is zero
         let mut pre_hash = H256::zero();
         // resource folder at the same place with genesis file
-        let resource_path = Path::new(path).parent().unwrap().join("resource");
+        let resource_path = spec_dir(Path::new(path)).join("resource");
         #[cfg(feature = "privatetx")]
         {
-            set_param_path(resource_path.join("PARAMS").to_str().unwrap());
+            set_param_path(&resource_path.join("PARAMS").to_string_lossy());
         }
         if resource_path.exists() {
-            let file_list_path = resource_path.join("files.list");
-            if file_list_path.exists() {
-                let file_list = File::open(file_list_path).unwrap();
-                let mut buf_reader = BufReader::new(file_list);
-                let mut contents = String::new();
-                buf_reader.read_to_string(&mut contents).unwrap();
-                let mut hasher = Md5::new();
-                for p in contents.lines() {
-                    let path = resource_path.join(p);
-                    let file = File::open(path).unwrap();
-                    let mut buf_reader = BufReader::new(file);
-                    let mut buf = Vec::new();
-                    buf_reader.read_to_end(&mut buf).unwrap();
-                    hasher.input(&buf);
-                }
-                let mut hash_str = "0x00000000000000000000000000000000".to_string();
-                hash_str += &hasher.result_str();
-                info!("resource hash {}", hash_str);
-                pre_hash = H256::from_unaligned(hash_str.as_str()).unwrap();
+            if let Some(hash) = resource_hash(&resource_path)? {
+                pre_hash = hash;
             }
         }
 
-        assert_eq!(pre_hash, spec.prevhash);
+        if pre_hash != spec.prevhash {
+            return Err(SpecError::InvalidPrevHash(Mismatch {
+                expected: spec.prevhash,
+                found: pre_hash,
+            }));
+        }
 
-        Genesis {
+        Ok(Genesis {
             spec,
+            spec_hash,
             block: Block::default(),
-        }
+        })
     }
 
     pub fn lazy_execute(
         &mut self,
         state_db: &StateDB,
         factories: &Factories,
-    ) -> Result<(), String> {
+    ) -> Result<(), SpecError> {
+        let accounts = parse_alloc(&self.spec.alloc)?;
         let mut state = State::from_existing(
             state_db.boxed_clone_canon(&self.spec.prevhash),
             *self.block.state_root(),
             U256::from(0),
             factories.clone(),
         )
-        .expect("state db error");
+        .map_err(state_error)?;
         self.block.set_version(0);
         self.block.set_parent_hash(self.spec.prevhash);
         self.block.set_timestamp(self.spec.timestamp);
@@ -123,53 +420,58 @@ impl Genesis {
 
         info!("This is the first time to init executor, and it will init contracts on height 0");
         trace!("**** begin **** \n");
-        for (address, contract) in self.spec.alloc.clone() {
-            let address = Address::from_unaligned(address.as_str()).unwrap();
-            state.new_contract(&address, U256::from(0), U256::from(0));
+        for account in &accounts {
+            state.new_contract(&account.address, U256::from(0), U256::from(0));
             {
                 state
-                    .init_code(&address, clean_0x(&contract.code).from_hex().unwrap())
-                    .expect("init code fail");
-                if let Some(value) = contract.value {
+                    .init_code(&account.address, account.code.clone())
+                    .map_err(state_error)?;
+                if let Some(ref value) = account.value {
                     state
-                        .add_balance(&address, &value)
-                        .expect("init balance fail");
+                        .add_balance(&account.address, value)
+                        .map_err(state_error)?;
                 }
             }
-            for (key, values) in contract.storage.clone() {
+            for &(key, value) in &account.storage {
                 state
-                    .set_storage(
-                        &address,
-                        H256::from_unaligned(key.as_ref()).unwrap(),
-                        H256::from_unaligned(values.as_ref()).unwrap(),
-                    )
-                    .expect("init code set_storage fail");
+                    .set_storage(&account.address, key, value)
+                    .map_err(state_error)?;
             }
         }
-        state.commit().expect("state commit error");
+        state.commit().map_err(state_error)?;
         //query is store in chain
-        for (address, contract) in &self.spec.alloc {
-            let address = Address::from_unaligned(address.as_str()).unwrap();
-            for (key, values) in &contract.storage {
-                let result =
-                    state.storage_at(&address, &H256::from_unaligned(key.as_ref()).unwrap());
-                assert_eq!(
-                    H256::from_unaligned(values.as_ref()).unwrap(),
-                    result.expect("storage error")
-                );
+        for account in &accounts {
+            for &(key, value) in &account.storage {
+                let found = state
+                    .storage_at(&account.address, &key)
+                    .map_err(state_error)?;
+                if found != value {
+                    return Err(SpecError::State(format!(
+                        "storage {:?} of {:?} is {:?}, expected {:?}",
+                        key, account.address, found, value
+                    )));
+                }
             }
         }
 
         trace!("**** end **** \n");
         let root = *state.root();
         trace!("root {:?}", root);
+        if let Some(expected) = self.spec.state_root {
+            if root != expected {
+                return Err(SpecError::InvalidStateRoot(Mismatch {
+                    expected,
+                    found: root,
+                }));
+            }
+        }
         self.block.set_state_root(root);
         self.block.rehash();
 
         self.save(state, state_db.journal_db().backing())
     }
 
-    fn save(&mut self, state: State<StateDB>, db: &Arc<KeyValueDB>) -> Result<(), String> {
+    fn save(&mut self, state: State<StateDB>, db: &Arc<KeyValueDB>) -> Result<(), SpecError> {
         let mut batch = db.transaction();
         let hash = self.block.hash().unwrap();
         let height = self.block.number();
@@ -180,18 +482,28 @@ impl Genesis {
         let mut state_db = state.drop().1;
         state_db
             .journal_under(&mut batch, height, &hash)
-            .expect("DB commit failed");
-        db.write(batch)
+            .map_err(state_error)?;
+        db.write(batch).map_err(SpecError::Database)
     }
 }
 
 #[cfg(test)]
 mod test {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+    use super::{alloc_from_state, merge_spec_value, normalize_spec_version};
+    use cita_db::trie::{TrieFactory, TrieSpec};
     use cita_types::{H256, U256};
-    use libexecutor::genesis::{Contract, Spec};
+    use factory::Factories;
+    use libexecutor::block::Block;
+    use libexecutor::genesis::{Contract, Genesis, Spec, SpecError};
     use serde_json;
+    use state::backend::Backend;
     use std::collections::HashMap;
+    use std::fs;
     use std::str::FromStr;
+    use tests::helpers::get_temp_state_db;
 
     #[test]
     fn test_spec() {
@@ -221,6 +533,7 @@ mod test {
             )
             .unwrap(),
             timestamp: 1524000000,
+            state_root: None,
             alloc: [
                 (
                     "0xffffffffffffffffffffffffffffffffff021019".to_owned(),
@@ -253,4 +566,271 @@ mod test {
         };
         assert_eq!(serde_json::from_value::<Spec>(genesis).unwrap(), spec);
     }
+
+    #[test]
+    fn test_merge_spec_value() {
+        let mut base = json!({
+            "timestamp": 1524000000,
+            "alloc": {
+                "0xffffffffffffffffffffffffffffffffff021019": {
+                    "nonce": "1",
+                    "code": "0x6060604052600436106100745763",
+                    "storage": {
+                        "0x00": "0x013241b2",
+                    }
+                },
+                "0x000000000000000000000000000000000a3241b6": {
+                    "nonce": "1",
+                    "code": "0x6060604052600436106100745763",
+                    "storage": {}
+                },
+            },
+            "prevhash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+        });
+        let overlay = json!({
+            "timestamp": 1524000001,
+            "alloc": {
+                "0xffffffffffffffffffffffffffffffffff021019": {
+                    "storage": {
+                        "0x01": "0x02",
+                    }
+                },
+                "0x000000000000000000000000000000000a3241b6": null,
+            },
+        });
+        merge_spec_value(&mut base, overlay);
+        assert_eq!(
+            base,
+            json!({
+                "timestamp": 1524000001,
+                "alloc": {
+                    "0xffffffffffffffffffffffffffffffffff021019": {
+                        "nonce": "1",
+                        "code": "0x6060604052600436106100745763",
+                        "storage": {
+                            "0x00": "0x013241b2",
+                            "0x01": "0x02",
+                        }
+                    },
+                },
+                "prevhash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            })
+        );
+    }
+
+    #[test]
+    fn test_normalize_spec_version() {
+        let mut legacy = json!({ "timestamp": 1524000000 });
+        assert!(normalize_spec_version(&mut legacy).is_ok());
+        assert_eq!(legacy["spec_version"], json!("1.0"));
+
+        let mut newer_minor = json!({ "spec_version": "1.3" });
+        assert!(normalize_spec_version(&mut newer_minor).is_ok());
+        assert_eq!(newer_minor["spec_version"], json!("1.3"));
+
+        let mut newer_major = json!({ "spec_version": "2.0" });
+        assert!(normalize_spec_version(&mut newer_major).is_err());
+
+        let mut invalid = json!({ "spec_version": "1" });
+        assert!(normalize_spec_version(&mut invalid).is_err());
+    }
+
+    #[test]
+    fn test_init_errors() {
+        let dir = TempDir::new("genesis").unwrap().into_path();
+
+        let missing = dir.join("missing.json");
+        match Genesis::init(missing.to_str().unwrap()) {
+            Err(SpecError::Io(..)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        fs::write(dir.join("a.json"), r#"{ "base": "b.json" }"#).unwrap();
+        fs::write(dir.join("b.json"), r#"{ "base": "a.json" }"#).unwrap();
+        match Genesis::init(dir.join("a.json").to_str().unwrap()) {
+            Err(SpecError::CyclicBase(..)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        fs::write(dir.join("c.json"), r#"{ "spec_version": "2.0" }"#).unwrap();
+        match Genesis::init(dir.join("c.json").to_str().unwrap()) {
+            Err(SpecError::InvalidVersion(..)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_init_invalid_alloc() {
+        let dir = TempDir::new("genesis").unwrap().into_path();
+        let path = dir.join("genesis.json");
+        let contracts = vec![
+            json!({ "0xinvalid": { "nonce": "1", "code": "0x", "storage": {} } }),
+            json!({
+                "0xffffffffffffffffffffffffffffffffff021019": {
+                    "nonce": "1", "code": "0xzz", "storage": {}
+                }
+            }),
+            json!({
+                "0xffffffffffffffffffffffffffffffffff021019": {
+                    "nonce": "1", "code": "0x6060", "storage": { "0x00": "0xzz" }
+                }
+            }),
+        ];
+        for alloc in contracts {
+            let genesis = json!({
+                "timestamp": 1524000000,
+                "prevhash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+                "alloc": alloc
+            });
+            fs::write(&path, genesis.to_string()).unwrap();
+            match Genesis::init(path.to_str().unwrap()) {
+                Err(SpecError::InvalidAlloc(..)) => {}
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_init_with_base() {
+        let dir = TempDir::new("genesis").unwrap().into_path();
+        fs::create_dir(dir.join("base")).unwrap();
+        fs::create_dir(dir.join("chain")).unwrap();
+
+        // base/root.json <- base/mid.json <- chain/genesis.json
+        let root = json!({
+            "timestamp": 1524000000,
+            "prevhash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "alloc": {
+                "0xffffffffffffffffffffffffffffffffff021019": {
+                    "nonce": "1",
+                    "code": "0x6060",
+                    "storage": { "0x00": "0x01" }
+                },
+                "0x000000000000000000000000000000000a3241b6": {
+                    "nonce": "1",
+                    "code": "0x6060",
+                    "storage": {}
+                }
+            }
+        });
+        // `root.json` is relative to `base/`, not to `chain/`.
+        let mid = json!({
+            "base": "root.json",
+            "alloc": {
+                "0xffffffffffffffffffffffffffffffffff021019": {
+                    "storage": { "0x01": "0x02" }
+                }
+            }
+        });
+        let genesis = json!({
+            "base": "../base/mid.json",
+            "timestamp": 1524000001,
+            "alloc": {
+                "0x000000000000000000000000000000000a3241b6": null
+            }
+        });
+        fs::write(dir.join("base/root.json"), root.to_string()).unwrap();
+        fs::write(dir.join("base/mid.json"), mid.to_string()).unwrap();
+        let path = dir.join("chain/genesis.json");
+        fs::write(&path, genesis.to_string()).unwrap();
+
+        let spec = Genesis::init(path.to_str().unwrap()).unwrap().spec;
+        assert_eq!(spec.timestamp, 1524000001);
+        assert_eq!(spec.alloc.len(), 1);
+        let contract = &spec.alloc["0xffffffffffffffffffffffffffffffffff021019"];
+        assert_eq!(contract.code, "0x6060");
+        assert_eq!(contract.storage.len(), 2);
+        assert_eq!(contract.storage["0x01"], "0x02");
+    }
+
+    fn genesis_with_state_root(state_root: Option<H256>) -> Genesis {
+        let spec = json!({
+            "timestamp": 1524000000,
+            "prevhash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "alloc": {
+                "0xffffffffffffffffffffffffffffffffff021019": {
+                    "nonce": "1",
+                    "code": "0x6060",
+                    "storage": { "0x00": "0x01" }
+                }
+            }
+        });
+        let mut spec: Spec = serde_json::from_value(spec).unwrap();
+        spec.state_root = state_root;
+        Genesis {
+            spec,
+            spec_hash: H256::default(),
+            block: Block::default(),
+        }
+    }
+
+    #[test]
+    fn test_lazy_execute_state_root() {
+        let factories = Factories::default();
+
+        let mut genesis = genesis_with_state_root(None);
+        genesis
+            .lazy_execute(&get_temp_state_db(), &factories)
+            .unwrap();
+        let root = *genesis.block.state_root();
+
+        let mut genesis = genesis_with_state_root(Some(root));
+        assert!(genesis
+            .lazy_execute(&get_temp_state_db(), &factories)
+            .is_ok());
+        assert_eq!(*genesis.block.state_root(), root);
+
+        let mut genesis = genesis_with_state_root(Some(H256::from(1)));
+        match genesis.lazy_execute(&get_temp_state_db(), &factories) {
+            Err(SpecError::InvalidStateRoot(ref mis)) if mis.found == root => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_alloc_from_state() {
+        let factories = Factories {
+            trie: TrieFactory::new(TrieSpec::Generic),
+            ..Default::default()
+        };
+        let mut genesis = genesis_with_state_root(None);
+        genesis.spec.alloc.insert(
+            "0x000000000000000000000000000000000a3241b6".to_owned(),
+            Contract {
+                nonce: "0".to_owned(),
+                code: "0x".to_owned(),
+                storage: HashMap::new(),
+                value: Some(U256::from(100)),
+            },
+        );
+        let state_db = get_temp_state_db();
+        genesis.lazy_execute(&state_db, &factories).unwrap();
+        let root = *genesis.block.state_root();
+
+        let alloc = alloc_from_state(state_db.as_hashdb(), &root).unwrap();
+        assert_eq!(alloc.len(), 2);
+        let contract = &alloc["0xffffffffffffffffffffffffffffffffff021019"];
+        assert_eq!(contract.code, "0x6060");
+        assert_eq!(
+            contract.storage["0x0000000000000000000000000000000000000000000000000000000000000000"],
+            "0x0000000000000000000000000000000000000000000000000000000000000001"
+        );
+
+        // Launch a new chain from the dumped alloc.
+        let spec = json!({
+            "timestamp": 1524000000,
+            "prevhash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+            "alloc": alloc,
+            "state_root": root,
+        });
+        let mut genesis = Genesis {
+            spec: serde_json::from_value(spec).unwrap(),
+            spec_hash: H256::default(),
+            block: Block::default(),
+        };
+        genesis
+            .lazy_execute(&get_temp_state_db(), &factories)
+            .unwrap();
+        assert_eq!(*genesis.block.state_root(), root);
+    }
 }
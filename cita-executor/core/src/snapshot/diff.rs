@@ -0,0 +1,406 @@
+// CITA
+// Copyright 2016-2018 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Diff of the states under two roots.
+//!
+//! Both tries are walked from their roots at once, and a subtree is skipped
+//! when both sides refer to the same node. Only the changed accounts are
+//! read, and the storage tries of an account are diffed the same way.
+
+use super::{pod_account, stored_bytes, Error};
+use account_db::AccountDB;
+use cita_db::trie::TrieError;
+use cita_db::HashDB;
+use cita_types::{Address, H256, U256};
+use hashable::HASH_NULL_RLP;
+use rlp::{DecoderError, UntrustedRlp};
+use std::collections::BTreeMap;
+use types::account_diff::{AccountDiff, Diff};
+use types::basic_account::BasicAccount;
+use types::state_diff::StateDiff;
+use util::Bytes;
+
+/// A decoded trie node. Paths are in nibbles, children are the raw RLP of
+/// the child reference: a node hash, an inline node or empty.
+enum Node {
+    Empty,
+    Leaf(Vec<u8>, Bytes),
+    Extension(Vec<u8>, Bytes),
+    Branch(Vec<Bytes>, Option<Bytes>),
+}
+
+impl Node {
+    fn decode(data: &[u8]) -> Result<Node, Error> {
+        let rlp = UntrustedRlp::new(data);
+        if rlp.is_empty() {
+            return Ok(Node::Empty);
+        }
+        match rlp.item_count()? {
+            2 => {
+                let (path, is_leaf) = decode_path(rlp.at(0)?.data()?);
+                if is_leaf {
+                    Ok(Node::Leaf(path, rlp.at(1)?.data()?.to_vec()))
+                } else {
+                    Ok(Node::Extension(path, rlp.at(1)?.as_raw().to_vec()))
+                }
+            }
+            17 => {
+                let mut children = Vec::with_capacity(16);
+                for i in 0..16 {
+                    children.push(rlp.at(i)?.as_raw().to_vec());
+                }
+                let value = rlp.at(16)?.data()?;
+                let value = if value.is_empty() {
+                    None
+                } else {
+                    Some(value.to_vec())
+                };
+                Ok(Node::Branch(children, value))
+            }
+            _ => Err(DecoderError::RlpIncorrectListLen.into()),
+        }
+    }
+}
+
+/// Decode a hex-prefix encoded path into nibbles, and whether it ends in a
+/// leaf.
+fn decode_path(encoded: &[u8]) -> (Vec<u8>, bool) {
+    let mut nibbles = Vec::with_capacity(encoded.len() * 2);
+    if encoded.is_empty() {
+        return (nibbles, false);
+    }
+    if encoded[0] & 0x10 != 0 {
+        nibbles.push(encoded[0] & 0x0f);
+    }
+    for byte in &encoded[1..] {
+        nibbles.push(byte >> 4);
+        nibbles.push(byte & 0x0f);
+    }
+    (nibbles, encoded[0] & 0x20 != 0)
+}
+
+fn nibbles_to_key(nibbles: &[u8]) -> Bytes {
+    nibbles
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair.get(1).cloned().unwrap_or(0))
+        .collect()
+}
+
+/// Resolve the node under `root`.
+fn root_node(db: &HashDB, root: &H256) -> Result<Bytes, Error> {
+    if *root == HASH_NULL_RLP {
+        return Ok(vec![0x80]);
+    }
+    db.get(root)
+        .map(|node| node.to_vec())
+        .ok_or_else(|| Error::Trie(TrieError::InvalidStateRoot(*root)))
+}
+
+/// Resolve the node a child reference points to.
+fn child_node(db: &HashDB, reference: &[u8]) -> Result<Bytes, Error> {
+    let rlp = UntrustedRlp::new(reference);
+    if rlp.is_list() || rlp.is_empty() {
+        return Ok(reference.to_vec());
+    }
+    let hash = rlp.as_val::<H256>()?;
+    db.get(&hash)
+        .map(|node| node.to_vec())
+        .ok_or_else(|| Error::Trie(TrieError::IncompleteDatabase(hash)))
+}
+
+/// Collect every key-value pair below `node`.
+fn collect_leaves(
+    db: &HashDB,
+    prefix: &mut Vec<u8>,
+    node: &[u8],
+    leaves: &mut BTreeMap<Bytes, Bytes>,
+) -> Result<(), Error> {
+    match Node::decode(node)? {
+        Node::Empty => {}
+        Node::Leaf(path, value) => {
+            let len = prefix.len();
+            prefix.extend(path);
+            leaves.insert(nibbles_to_key(prefix), value);
+            prefix.truncate(len);
+        }
+        Node::Extension(path, child) => {
+            let len = prefix.len();
+            prefix.extend(path);
+            collect_leaves(db, prefix, &child_node(db, &child)?, leaves)?;
+            prefix.truncate(len);
+        }
+        Node::Branch(children, value) => {
+            if let Some(value) = value {
+                leaves.insert(nibbles_to_key(prefix), value);
+            }
+            for (i, child) in children.iter().enumerate() {
+                prefix.push(i as u8);
+                collect_leaves(db, prefix, &child_node(db, child)?, leaves)?;
+                prefix.pop();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Diff the subtries below `pre` and `post`, both found at `prefix`.
+fn diff_nodes(
+    db: &HashDB,
+    prefix: &mut Vec<u8>,
+    pre: &[u8],
+    post: &[u8],
+    changes: &mut BTreeMap<Bytes, Diff<Bytes>>,
+) -> Result<(), Error> {
+    if pre == post {
+        return Ok(());
+    }
+    match (Node::decode(pre)?, Node::decode(post)?) {
+        (Node::Branch(pre_children, pre_value), Node::Branch(post_children, post_value)) => {
+            for (i, (pre_child, post_child)) in pre_children.iter().zip(&post_children).enumerate()
+            {
+                if pre_child != post_child {
+                    let pre_child = child_node(db, pre_child)?;
+                    let post_child = child_node(db, post_child)?;
+                    prefix.push(i as u8);
+                    diff_nodes(db, prefix, &pre_child, &post_child, changes)?;
+                    prefix.pop();
+                }
+            }
+            let key = nibbles_to_key(prefix);
+            match (pre_value, post_value) {
+                (Some(pre), Some(post)) => {
+                    if pre != post {
+                        changes.insert(key, Diff::Changed(pre, post));
+                    }
+                }
+                (Some(pre), None) => {
+                    changes.insert(key, Diff::Died(pre));
+                }
+                (None, Some(post)) => {
+                    changes.insert(key, Diff::Born(post));
+                }
+                (None, None) => {}
+            }
+        }
+        (
+            Node::Extension(ref pre_path, ref pre_child),
+            Node::Extension(ref post_path, ref post_child),
+        ) if pre_path == post_path => {
+            let pre_child = child_node(db, pre_child)?;
+            let post_child = child_node(db, post_child)?;
+            let len = prefix.len();
+            prefix.extend(pre_path);
+            diff_nodes(db, prefix, &pre_child, &post_child, changes)?;
+            prefix.truncate(len);
+        }
+        _ => {
+            // The shapes differ, compare the pairs below both sides.
+            let mut pre_leaves = BTreeMap::new();
+            collect_leaves(db, prefix, pre, &mut pre_leaves)?;
+            let mut post_leaves = BTreeMap::new();
+            collect_leaves(db, prefix, post, &mut post_leaves)?;
+            for (key, pre_value) in pre_leaves {
+                match post_leaves.remove(&key) {
+                    Some(post_value) => {
+                        if pre_value != post_value {
+                            changes.insert(key, Diff::Changed(pre_value, post_value));
+                        }
+                    }
+                    None => {
+                        changes.insert(key, Diff::Died(pre_value));
+                    }
+                }
+            }
+            for (key, post_value) in post_leaves {
+                changes.insert(key, Diff::Born(post_value));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Diff the tries under `pre` and `post`, returning the changed values by key.
+fn diff_trie(db: &HashDB, pre: &H256, post: &H256) -> Result<BTreeMap<Bytes, Diff<Bytes>>, Error> {
+    let mut changes = BTreeMap::new();
+    if pre != post {
+        let pre = root_node(db, pre)?;
+        let post = root_node(db, post)?;
+        diff_nodes(db, &mut Vec::new(), &pre, &post, &mut changes)?;
+    }
+    Ok(changes)
+}
+
+fn storage_value(value: &[u8]) -> Result<H256, Error> {
+    Ok(UntrustedRlp::new(value).as_val::<U256>()?.into())
+}
+
+/// Diff the states under `pre` and `post`, which are both stored in `db`.
+///
+/// The storage of an account is only listed by the slots which changed.
+/// An account whose abi is the only change is left out, as `AccountDiff`
+/// does not cover the abi.
+pub fn state_diff(db: &HashDB, pre: &H256, post: &H256) -> Result<StateDiff, Error> {
+    let mut raw = BTreeMap::new();
+
+    for (key, diff) in diff_trie(db, pre, post)? {
+        let address = Address::from_slice(&key);
+        let account_diff = match diff {
+            Diff::Born(data) => {
+                let account = UntrustedRlp::new(&data).as_val::<BasicAccount>()?;
+                let pod = pod_account(db, &address, &account)?;
+                AccountDiff {
+                    balance: Diff::Born(pod.balance),
+                    nonce: Diff::Born(pod.nonce),
+                    code: Diff::Born(pod.code.unwrap_or_default()),
+                    storage: pod
+                        .storage
+                        .into_iter()
+                        .map(|(key, value)| (key, Diff::Born(value)))
+                        .collect(),
+                }
+            }
+            Diff::Died(data) => {
+                let account = UntrustedRlp::new(&data).as_val::<BasicAccount>()?;
+                let pod = pod_account(db, &address, &account)?;
+                AccountDiff {
+                    balance: Diff::Died(pod.balance),
+                    nonce: Diff::Died(pod.nonce),
+                    code: Diff::Died(pod.code.unwrap_or_default()),
+                    storage: pod
+                        .storage
+                        .into_iter()
+                        .map(|(key, value)| (key, Diff::Died(value)))
+                        .collect(),
+                }
+            }
+            Diff::Changed(pre_data, post_data) => {
+                let pre = UntrustedRlp::new(&pre_data).as_val::<BasicAccount>()?;
+                let post = UntrustedRlp::new(&post_data).as_val::<BasicAccount>()?;
+                let account_db = AccountDB::new(db, &address);
+                let code = if pre.code_hash == post.code_hash {
+                    Diff::Same
+                } else {
+                    let missing = || Error::MissingCode(vec![address]);
+                    Diff::new(
+                        stored_bytes(&account_db, &pre.code_hash).ok_or_else(missing)?,
+                        stored_bytes(&account_db, &post.code_hash).ok_or_else(missing)?,
+                    )
+                };
+                let mut storage = BTreeMap::new();
+                for (key, diff) in diff_trie(&account_db, &pre.storage_root, &post.storage_root)? {
+                    let diff = match diff {
+                        Diff::Born(value) => Diff::Born(storage_value(&value)?),
+                        Diff::Died(value) => Diff::Died(storage_value(&value)?),
+                        Diff::Changed(pre, post) => {
+                            Diff::Changed(storage_value(&pre)?, storage_value(&post)?)
+                        }
+                        Diff::Same => continue,
+                    };
+                    storage.insert(H256::from_slice(&key), diff);
+                }
+                AccountDiff {
+                    balance: Diff::new(pre.balance, post.balance),
+                    nonce: Diff::new(pre.nonce, post.nonce),
+                    code,
+                    storage,
+                }
+            }
+            Diff::Same => continue,
+        };
+        if account_diff.balance.is_same()
+            && account_diff.nonce.is_same()
+            && account_diff.code.is_same()
+            && account_diff.storage.is_empty()
+        {
+            continue;
+        }
+        raw.insert(address, account_diff);
+    }
+
+    Ok(StateDiff { raw })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cita_db::trie::{TrieFactory, TrieSpec};
+    use factory::Factories;
+    use state::backend::Backend;
+    use state::State;
+    use tests::helpers::get_temp_state_db;
+
+    #[test]
+    fn test_state_diff() {
+        let factories = Factories {
+            trie: TrieFactory::new(TrieSpec::Generic),
+            ..Default::default()
+        };
+        let (alice, bob, contract) = (Address::from(1), Address::from(2), Address::from(3));
+        let mut state = State::new(get_temp_state_db(), U256::zero(), factories.clone());
+        state.add_balance(&alice, &U256::from(100)).unwrap();
+        state.new_contract(&contract, U256::zero(), U256::zero());
+        state.init_code(&contract, vec![0x60, 0x00]).unwrap();
+        for slot in 0..20u64 {
+            state
+                .set_storage(&contract, H256::from(slot), H256::from(slot + 1))
+                .unwrap();
+        }
+        state.commit().unwrap();
+        let pre = *state.root();
+        let (_, state_db) = state.drop();
+
+        let mut state = State::from_existing(state_db, pre, U256::zero(), factories).unwrap();
+        state.sub_balance(&alice, &U256::from(30)).unwrap();
+        state.add_balance(&bob, &U256::from(30)).unwrap();
+        state
+            .set_storage(&contract, H256::from(1), H256::from(100))
+            .unwrap();
+        state
+            .set_storage(&contract, H256::from(2), H256::zero())
+            .unwrap();
+        state
+            .set_storage(&contract, H256::from(20), H256::from(21))
+            .unwrap();
+        state.commit().unwrap();
+        let post = *state.root();
+        let (_, state_db) = state.drop();
+        let db = state_db.as_hashdb();
+
+        assert!(state_diff(db, &pre, &pre).unwrap().is_empty());
+
+        let diff = state_diff(db, &pre, &post).unwrap();
+        assert_eq!(diff.len(), 3);
+        assert_eq!(
+            diff[&alice].balance,
+            Diff::Changed(U256::from(100), U256::from(70))
+        );
+        assert!(diff[&alice].storage.is_empty());
+        assert_eq!(diff[&bob].balance, Diff::Born(U256::from(30)));
+
+        let contract_diff = &diff[&contract];
+        assert!(contract_diff.balance.is_same());
+        assert!(contract_diff.code.is_same());
+        let mut storage = BTreeMap::new();
+        storage.insert(H256::from(1), Diff::Changed(H256::from(2), H256::from(100)));
+        storage.insert(H256::from(2), Diff::Died(H256::from(3)));
+        storage.insert(H256::from(20), Diff::Born(H256::from(21)));
+        assert_eq!(contract_diff.storage, storage);
+
+        let diff = state_diff(db, &post, &pre).unwrap();
+        assert_eq!(diff[&bob].balance, Diff::Died(U256::from(30)));
+    }
+}
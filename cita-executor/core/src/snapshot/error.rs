@@ -36,6 +36,8 @@ pub enum Error {
     IncompleteChain,
     /// Best block has wrong state root.
     WrongStateRoot(H256, H256),
+    /// Account has wrong storage root.
+    WrongStorageRoot(Address, H256, H256),
     /// Wrong block hash.
     WrongBlockHash(u64, H256, H256),
     /// Too many blocks contained within the snapshot.
@@ -81,6 +83,11 @@ impl fmt::Display for Error {
                 "Final block has wrong state root. Expected {:?}, got {:?}",
                 expected, found
             ),
+            Error::WrongStorageRoot(ref address, ref expected, ref found) => write!(
+                f,
+                "Account {:?} has wrong storage root. Expected {:?}, got {:?}",
+                address, expected, found
+            ),
             Error::WrongBlockHash(ref num, ref expected, ref found) => write!(
                 f,
                 "Block {} had wrong hash. expected {:?}, got {:?}",
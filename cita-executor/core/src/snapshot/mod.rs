@@ -24,24 +24,28 @@ use account_db::{AccountDB, AccountDBMut};
 use cita_db::hashdb::DBValue;
 use cita_db::journaldb::{self, Algorithm, JournalDB};
 use cita_db::kvdb::{DBTransaction, Database, KeyValueDB};
-use cita_db::{HashDB, Trie, TrieDB, TrieDBMut, TrieMut};
+use cita_db::{HashDB, MemoryDB, Trie, TrieDB, TrieDBMut, TrieMut};
 use cita_types::{Address, H256, U256};
-use db::{Writable, COL_EXTRA, COL_HEADERS, COL_STATE};
+use db::{Readable, Writable, COL_EXTRA, COL_HEADERS, COL_STATE};
 use hashable::Hashable;
 use hashable::{HASH_EMPTY, HASH_NULL_RLP};
 use libexecutor::executor::Executor;
+use pod_account::PodAccount;
 use rlp::{DecoderError, Encodable, RlpStream, UntrustedRlp};
 use snappy;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::Range;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use util::{Bytes, Mutex};
 
 pub mod account;
+pub mod diff;
 pub mod error;
 pub mod io;
 pub mod service;
+pub use self::diff::state_diff;
 pub use self::error::Error;
 use self::io::SnapshotReader;
 use self::io::SnapshotWriter;
@@ -334,6 +338,154 @@ pub fn chunk_state<'a>(
     Ok(chunker.hashes)
 }
 
+/// Rebuild the trie under `root` from its stored key-value pairs and return
+/// the root of the rebuilt trie.
+fn rebuild_trie_root(db: &HashDB, root: &H256) -> Result<H256, Error> {
+    let trie = TrieDB::new(db, root).map_err(|err| *err)?;
+    let mut rebuilt_db = MemoryDB::new();
+    let mut rebuilt_root = HASH_NULL_RLP;
+    {
+        let mut rebuilt = TrieDBMut::new(&mut rebuilt_db, &mut rebuilt_root);
+        for item in trie.iter().map_err(|err| *err)? {
+            let (key, value) = item.map_err(|err| *err)?;
+            rebuilt.insert(&key, &value).map_err(|err| *err)?;
+        }
+    }
+    Ok(rebuilt_root)
+}
+
+/// Recompute the state root under `root` from the trie nodes stored in `db`.
+///
+/// The storage trie of every account is rebuilt and checked against the
+/// account's storage root, and its code and abi are looked up. Returns the
+/// root of the rebuilt account trie, which differs from `root` if a stored
+/// node has been corrupted.
+pub fn recompute_state_root(db: &HashDB, root: &H256) -> Result<H256, Error> {
+    let account_trie = TrieDB::new(db, root).map_err(|err| *err)?;
+    let mut rebuilt_db = MemoryDB::new();
+    let mut rebuilt_root = HASH_NULL_RLP;
+    {
+        let mut rebuilt = TrieDBMut::new(&mut rebuilt_db, &mut rebuilt_root);
+
+        // account_key here is the address.
+        for item in account_trie.iter().map_err(|err| *err)? {
+            let (account_key, account_data) = item.map_err(|err| *err)?;
+
+            let account = UntrustedRlp::new(&account_data).as_val::<BasicAccount>()?;
+            let account_address = Address::from_slice(&account_key);
+            let account_db = AccountDB::new(db, &account_address);
+
+            let storage_root = rebuild_trie_root(&account_db, &account.storage_root)?;
+            if storage_root != account.storage_root {
+                return Err(Error::WrongStorageRoot(
+                    account_address,
+                    account.storage_root,
+                    storage_root,
+                ));
+            }
+            if account.code_hash != HASH_EMPTY && !account_db.contains(&account.code_hash) {
+                return Err(Error::MissingCode(vec![account_address]));
+            }
+            if account.abi_hash != HASH_EMPTY && !account_db.contains(&account.abi_hash) {
+                return Err(Error::MissingAbi(vec![account_address]));
+            }
+
+            rebuilt
+                .insert(&account_key, &account_data)
+                .map_err(|err| *err)?;
+        }
+    }
+    Ok(rebuilt_root)
+}
+
+/// Walk the state under `root` and call `f` with every account, including
+/// its code, abi and storage.
+///
+/// The executor builds its tries with `TrieSpec::Generic`, so the account
+/// trie is keyed by address and each storage trie by slot.
+pub fn iterate_state<F>(db: &HashDB, root: &H256, mut f: F) -> Result<(), Error>
+where
+    F: FnMut(Address, PodAccount) -> Result<(), Error>,
+{
+    let account_trie = TrieDB::new(db, root).map_err(|err| *err)?;
+
+    for item in account_trie.iter().map_err(|err| *err)? {
+        let (account_key, account_data) = item.map_err(|err| *err)?;
+
+        let account = UntrustedRlp::new(&account_data).as_val::<BasicAccount>()?;
+        let account_address = Address::from_slice(&account_key);
+        let pod = pod_account(db, &account_address, &account)?;
+        f(account_address, pod)?;
+    }
+    Ok(())
+}
+
+/// Read the code, abi and storage of `account`.
+fn pod_account(
+    db: &HashDB,
+    address: &Address,
+    account: &BasicAccount,
+) -> Result<PodAccount, Error> {
+    let account_db = AccountDB::new(db, address);
+
+    let mut storage = BTreeMap::new();
+    let storage_trie = TrieDB::new(&account_db, &account.storage_root).map_err(|err| *err)?;
+    for item in storage_trie.iter().map_err(|err| *err)? {
+        let (key, value) = item.map_err(|err| *err)?;
+        let value = UntrustedRlp::new(&value).as_val::<U256>()?;
+        storage.insert(H256::from_slice(&key), value.into());
+    }
+
+    let code = stored_bytes(&account_db, &account.code_hash)
+        .ok_or_else(|| Error::MissingCode(vec![*address]))?;
+    let abi = stored_bytes(&account_db, &account.abi_hash)
+        .ok_or_else(|| Error::MissingAbi(vec![*address]))?;
+
+    Ok(PodAccount {
+        balance: account.balance,
+        nonce: account.nonce,
+        code: Some(code),
+        abi: Some(abi),
+        storage,
+    })
+}
+
+fn stored_bytes(db: &HashDB, hash: &H256) -> Option<Bytes> {
+    if *hash == HASH_EMPTY {
+        Some(Vec::new())
+    } else {
+        db.get(hash).map(|value| value.to_vec())
+    }
+}
+
+/// Check the state of the blocks in `heights` against their headers,
+/// recomputing each state root from the stored trie nodes.
+///
+/// Returns the first block which fails, with the reason. The state of old
+/// blocks is only kept by an archive state database.
+pub fn verify_state_roots(
+    db: &KeyValueDB,
+    state_db: &StateDB,
+    heights: Range<u64>,
+) -> Result<(), (u64, Error)> {
+    let hash_db = state_db.as_hashdb();
+
+    for height in heights {
+        let header = db
+            .read(COL_EXTRA, &height)
+            .and_then(|hash: H256| db.read::<Header, _>(COL_HEADERS, &hash))
+            .ok_or_else(|| (height, Error::InvalidStartingBlock(BlockId::Number(height))))?;
+        let state_root = *header.state_root();
+        let found = recompute_state_root(hash_db, &state_root).map_err(|err| (height, err))?;
+        if found != state_root {
+            return Err((height, Error::WrongStateRoot(state_root, found)));
+        }
+        trace!("state of {}-th block verified", height);
+    }
+
+    Ok(())
+}
+
 /// Used to build block chunks.
 struct BlockChunker<'a> {
     executor: &'a Executor,
@@ -973,3 +1125,118 @@ pub fn restore_using<R: SnapshotReader>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cita_db::trie::{TrieFactory, TrieSpec};
+    use factory::Factories;
+    use state::State;
+    use tests::helpers::get_temp_state_db;
+
+    #[test]
+    fn test_recompute_state_root() {
+        let factories = Factories {
+            trie: TrieFactory::new(TrieSpec::Generic),
+            ..Default::default()
+        };
+        let mut state = State::new(get_temp_state_db(), U256::zero(), factories);
+        let account = Address::from(1);
+        let contract = Address::from(2);
+        state.add_balance(&account, &U256::from(100)).unwrap();
+        state.new_contract(&contract, U256::zero(), U256::zero());
+        state.init_code(&contract, vec![0x60, 0x00]).unwrap();
+        state
+            .set_storage(&contract, H256::from(1), H256::from(2))
+            .unwrap();
+        state.commit().unwrap();
+
+        let (root, state_db) = state.drop();
+        let hash_db = state_db.as_hashdb();
+        assert_eq!(recompute_state_root(hash_db, &root).unwrap(), root);
+        assert!(recompute_state_root(hash_db, &H256::from(3)).is_err());
+    }
+
+    // Build a state of one contract holding `value` in slot 1.
+    fn build_state(db: &mut MemoryDB, contract: &Address, value: H256) -> (H256, BasicAccount) {
+        let mut storage_root = HASH_NULL_RLP;
+        let code_hash = {
+            let mut account_db = AccountDBMut::new(db, contract);
+            {
+                let mut storage = TrieDBMut::new(&mut account_db, &mut storage_root);
+                storage
+                    .insert(&H256::from(1), &::rlp::encode(&value))
+                    .unwrap();
+            }
+            account_db.insert(&[0x60, 0x00])
+        };
+        let account = BasicAccount {
+            nonce: U256::zero(),
+            balance: U256::from(100),
+            storage_root,
+            code_hash,
+            abi_hash: HASH_EMPTY,
+        };
+        let mut root = HASH_NULL_RLP;
+        TrieDBMut::new(db, &mut root)
+            .insert(contract, &::rlp::encode(&account))
+            .unwrap();
+        (root, account)
+    }
+
+    // Replace the node stored under `key` with the root node of `other`.
+    fn tamper(db: &mut HashDB, key: H256, other: &HashDB, other_root: &H256) {
+        let node = other.get(other_root).unwrap();
+        db.remove(&key);
+        db.emplace(key, node);
+    }
+
+    #[test]
+    fn test_recompute_tampered_state() {
+        let contract = Address::from(2);
+        let (root, account) = build_state(&mut MemoryDB::new(), &contract, H256::from(2));
+
+        // A storage node holding another value.
+        let mut db = MemoryDB::new();
+        build_state(&mut db, &contract, H256::from(2));
+        let mut other = MemoryDB::new();
+        let (_, other_account) = build_state(&mut other, &contract, H256::from(3));
+        tamper(
+            &mut AccountDBMut::new(&mut db, &contract),
+            account.storage_root,
+            &AccountDB::new(&other, &contract),
+            &other_account.storage_root,
+        );
+        match recompute_state_root(&db, &root) {
+            Err(Error::WrongStorageRoot(address, expected, found)) => {
+                assert_eq!(address, contract);
+                assert_eq!(expected, account.storage_root);
+                assert_eq!(found, other_account.storage_root);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // A missing code.
+        let mut db = MemoryDB::new();
+        build_state(&mut db, &contract, H256::from(2));
+        AccountDBMut::new(&mut db, &contract).remove(&account.code_hash);
+        match recompute_state_root(&db, &root) {
+            Err(Error::MissingCode(ref missing)) if *missing == vec![contract] => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // An account leaf which is not an account.
+        let mut db = MemoryDB::new();
+        build_state(&mut db, &contract, H256::from(2));
+        let mut other = MemoryDB::new();
+        let mut other_root = HASH_NULL_RLP;
+        TrieDBMut::new(&mut other, &mut other_root)
+            .insert(&contract, b"bogus")
+            .unwrap();
+        tamper(&mut db, root, &other, &other_root);
+        match recompute_state_root(&db, &root) {
+            Err(Error::Decoder(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
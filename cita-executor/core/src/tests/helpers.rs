@@ -24,7 +24,7 @@ use cita_crypto::PrivKey;
 use cita_db::kvdb::{self, Database, DatabaseConfig};
 use cita_db::KeyValueDB;
 use cita_types::traits::LowerHex;
-use cita_types::{Address, U256};
+use cita_types::{Address, H256, U256};
 use core::libchain::chain;
 use crossbeam_channel::{Receiver, Sender};
 use db;
@@ -191,6 +191,7 @@ pub fn init_executor2(
     let spec: Spec = serde_json::from_reader(genesis_file).expect("Failed to load genesis.");
     let _genesis = Genesis {
         spec,
+        spec_hash: H256::default(),
         block: Block::default(),
     };
 
@@ -207,7 +208,8 @@ pub fn init_executor2(
         command_req_receiver,
         command_resp_sender,
         false,
-    );
+    )
+    .expect("failed to init executor");
     executor
 }
 
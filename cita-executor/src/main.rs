@@ -110,14 +110,24 @@ extern crate serde_json;
 #[macro_use]
 extern crate util;
 
+use cita_db::KeyValueDB;
 use cita_directories::DataPath;
 use clap::App;
 use core::contracts::grpc::grpc_vm_adapter;
-use core::libexecutor::executor::Executor;
+use core::db::COL_STATE;
+use core::journaldb;
+use core::libexecutor::executor::{get_current_header, open_state_db, Executor};
+use core::libexecutor::genesis::Genesis;
+use core::snapshot::verify_state_roots;
+use core::state_db::StateDB;
 use libproto::router::{MsgType, RoutingKey, SubModules};
 use postman::Postman;
 use pubsub::start_pubsub;
+use std::ops::Range;
+use std::path::Path;
+use std::process;
 use std::sync::mpsc::channel;
+use std::sync::Arc;
 use std::thread;
 use util::set_panic_handler;
 
@@ -156,6 +166,41 @@ impl Options {
     }
 }
 
+fn parse_heights(heights: &str) -> Option<Range<u64>> {
+    let mut bounds = heights.splitn(2, "..");
+    let from = bounds.next()?.trim().parse().ok()?;
+    let to = bounds.next()?.trim().parse().ok()?;
+    Some(from..to)
+}
+
+// Open the state database as it is, never writing a genesis block into it.
+fn verify_state(options: &Options, heights: Range<u64>) {
+    let data_path = DataPath::root_node_path();
+    if !Path::new(&data_path).join("statedb").is_dir() {
+        eprintln!("No state database found in {}.", data_path);
+        process::exit(1);
+    }
+    let database: Arc<KeyValueDB> = Arc::new(open_state_db(data_path));
+    if get_current_header(&*database).is_none() {
+        eprintln!("No block found in the state database.");
+        process::exit(1);
+    }
+    let journaldb_type = options
+        .journaldb_type
+        .parse()
+        .unwrap_or(journaldb::Algorithm::Archive);
+    let journal_db = journaldb::new(Arc::clone(&database), journaldb_type, COL_STATE);
+    let state_db = StateDB::new(journal_db, options.statedb_cache_size);
+    let (from, to) = (heights.start, heights.end);
+    match verify_state_roots(&*database, &state_db, heights) {
+        Ok(()) => println!("State of blocks {}..{} verified.", from, to),
+        Err((height, err)) => {
+            eprintln!("State of {}-th block diverges: {}", height, err);
+            process::exit(1);
+        }
+    }
+}
+
 fn main() {
     micro_service_init!("cita-executor", "CITA:executor");
     let matches = App::new("executor")
@@ -164,9 +209,34 @@ fn main() {
         .author("Cryptape")
         .about("CITA Block Chain Node powered by Rust")
         .arg_from_usage("-c, --config=[FILE] 'Sets a switch config file'")
+        .arg_from_usage("--spec-hash 'Prints the hash of the normalized genesis spec and exits'")
+        .arg_from_usage(
+            "--verify-state=[HEIGHTS] 'Checks the state roots of blocks FROM..TO (TO excluded) \
+             against the stored state and exits'",
+        )
         .get_matches();
     let config_path = matches.value_of("config").unwrap_or("executor.toml");
     let options = Options::load(config_path);
+
+    // Validators of one chain must print the same hash.
+    if matches.is_present("spec-hash") {
+        let genesis = Genesis::init(&options.genesis_path).unwrap_or_else(|err| {
+            eprintln!("Failed to load genesis: {}.", err);
+            process::exit(1);
+        });
+        println!("{:?}", genesis.spec_hash);
+        return;
+    }
+
+    // Run it on a stopped node with an archive state database.
+    if let Some(heights) = matches.value_of("verify-state") {
+        let heights = parse_heights(heights).unwrap_or_else(|| {
+            eprintln!("Invalid heights {}, expected FROM..TO.", heights);
+            process::exit(1);
+        });
+        verify_state(&options, heights);
+        return;
+    }
     info!("Version: {}", get_build_info_str(true));
     info!("Config: {:?}", options);
 
@@ -235,7 +305,11 @@ fn main() {
             command_req_receiver.clone(),
             command_resp_sender.clone(),
             options.eth_compatibility,
-        );
+        )
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to load genesis: {}.", err);
+            process::exit(1);
+        });
         let current_height = executor.get_current_height();
         let current_hash = executor.get_current_hash();
         let handle = thread::spawn(move || {
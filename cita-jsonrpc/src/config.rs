@@ -15,9 +15,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
 use std::convert::Into;
 use ws::Settings;
 
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 32;
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 256;
+pub const DEFAULT_READY_BLOCK_INTERVALS: u64 = 10;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub backlog_capacity: usize,
@@ -25,6 +30,7 @@ pub struct Config {
     pub http_config: HttpConfig,
     pub ws_config: WsConfig,
     pub new_tx_flow_config: NewTxFlowConfig,
+    pub rate_limit_config: Option<RateLimitConfig>,
 }
 
 impl Config {
@@ -39,6 +45,17 @@ pub struct NewTxFlowConfig {
     pub buffer_duration: u32, //in unit of ns
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RateLimitConfig {
+    pub enable: bool,
+    pub ip_rate: u32,
+    pub ip_burst: u32,
+    #[serde(default)]
+    pub method_weights: HashMap<String, u32>,
+    #[serde(default)]
+    pub method_rates: HashMap<String, u32>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct ProfileConfig {
     pub enable: bool,
@@ -118,4 +135,7 @@ pub struct HttpConfig {
     pub listen_port: String,
     pub timeout: u64,
     pub allow_origin: Option<String>,
+    pub batch_concurrency: Option<usize>,
+    pub max_batch_size: Option<usize>,
+    pub ready_block_intervals: Option<u64>,
 }
@@ -16,24 +16,31 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use futures::future::{self as future, Future};
+use futures::Stream;
 use hyper::header::{
     HeaderMap as Headers, HeaderName, HeaderValue, ACCEPT, ACCESS_CONTROL_ALLOW_HEADERS,
     ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_MAX_AGE, CONTENT_TYPE, ORIGIN, USER_AGENT,
 };
+use hyper::server::conn::AddrStream;
 use hyper::service::{MakeService, Service};
 use hyper::{Body, Method, Request, Response, StatusCode};
 use jsonrpc_types::{request::RpcRequest as JsonrpcRequest, rpctypes::Id as RpcId};
 use libproto::request::Request as ProtoRequest;
-use std::net::{SocketAddr, TcpListener};
+use serde_json;
+use std::net::{IpAddr, SocketAddr, TcpListener};
 use std::sync::{mpsc, Arc};
 use std::time::Duration;
+use util::instrument::{unix_now, AsMillis};
 use util::Mutex;
 
 use crate::extractor::FutExtractor;
 use crate::helper::{ReqSender, RpcMap};
 use crate::http_header::{Origin, CONTENT_TYPE_JSON_STR, CONTENT_TYPE_PLAIN_TEXT_STR};
 use crate::mq_publisher::{AccessLog as MQAccessLog, MQRequest, Publisher, TimeoutPublisher};
+use crate::rate_limiter::{RateLimitError, RateLimiter};
+use crate::readiness::{Readiness, READINESS_PROBE};
 use crate::response::{HyperResponseExt, IntoResponse};
+use crate::service_error::ServiceError;
 
 const TCP_BACKLOG: i32 = 1024;
 const CORS_CACHE: u32 = 86_400u32;
@@ -42,18 +49,23 @@ struct Inner {
     pub tx: ReqSender,
     pub responses: RpcMap,
     pub timeout: Duration,
+    pub batch_concurrency: usize,
+    pub max_batch_size: usize,
+    pub ready_block_intervals: u64,
     pub http_headers: Headers,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 pub struct Jsonrpc {
     inner: Arc<Inner>,
+    remote_ip: IpAddr,
 }
 
 pub struct JsonrpcMakeService {
     inner: Arc<Inner>,
 }
 
-impl<Ctx> MakeService<Ctx> for JsonrpcMakeService {
+impl<'a> MakeService<&'a AddrStream> for JsonrpcMakeService {
     type ReqBody = Body;
     type ResBody = Body;
     type Error = hyper::Error;
@@ -61,9 +73,10 @@ impl<Ctx> MakeService<Ctx> for JsonrpcMakeService {
     type Future = Box<dyn Future<Item = Self::Service, Error = Self::Error> + Send>;
     type MakeError = hyper::Error;
 
-    fn make_service(&mut self, _: Ctx) -> Self::Future {
+    fn make_service(&mut self, conn: &'a AddrStream) -> Self::Future {
         Box::new(future::ok(Jsonrpc {
             inner: Arc::clone(&self.inner),
+            remote_ip: conn.remote_addr().ip(),
         }))
     }
 }
@@ -159,7 +172,12 @@ impl Service for Jsonrpc {
         let sender = { self.inner.tx.lock().clone() };
         let responses = Arc::clone(&self.inner.responses);
         let timeout = self.inner.timeout;
+        let batch_concurrency = self.inner.batch_concurrency;
+        let max_batch_size = self.inner.max_batch_size;
+        let ready_block_intervals = self.inner.ready_block_intervals;
         let http_headers = self.inner.http_headers.clone();
+        let rate_limiter = self.inner.rate_limiter.clone();
+        let remote_ip = self.remote_ip;
 
         let http_path = http_req.uri().path().to_owned();
         let mut access_log = AccessLog::new(http_req.method(), &http_path, &http_headers);
@@ -168,6 +186,32 @@ impl Service for Jsonrpc {
             (&Method::POST, "/") => {
                 let fut_resp = FutExtractor::<JsonrpcRequest>::extract_from(http_req)
                     .and_then(FutExtractor::<MQRequest>::extract_from)
+                    .and_then(move |mq_req| match mq_req {
+                        MQRequest::Batch(ref reqs) if reqs.len() > max_batch_size => {
+                            warn!(
+                                "batch of {} calls from {} is too large",
+                                reqs.len(),
+                                remote_ip
+                            );
+                            Err(ServiceError::BatchTooLarge)
+                        }
+                        _ => Ok(mq_req),
+                    })
+                    .and_then(move |mq_req| match rate_limiter {
+                        Some(ref limiter) => match limiter.check(remote_ip, &mq_req.methods()) {
+                            Ok(()) => Ok(mq_req),
+                            Err(RateLimitError::Exhausted) => {
+                                warn!("rate limited request from {}", remote_ip);
+                                let retry_after = limiter.retry_after(&mq_req.methods());
+                                Err(ServiceError::RateLimited(retry_after))
+                            }
+                            Err(RateLimitError::TooLarge) => {
+                                warn!("request from {} exceeds the rate limit", remote_ip);
+                                Err(ServiceError::RequestTooLarge)
+                            }
+                        },
+                        None => Ok(mq_req),
+                    })
                     .and_then({
                         let headers = http_headers.clone();
 
@@ -177,7 +221,8 @@ impl Service for Jsonrpc {
                             info!("{}", access_log);
 
                             let timeout_responses = Arc::clone(&responses);
-                            let pulibsher = Publisher::new(responses, sender, headers);
+                            let pulibsher =
+                                Publisher::new(responses, sender, headers, batch_concurrency);
                             let pulibsher =
                                 TimeoutPublisher::new(pulibsher, timeout, timeout_responses);
 
@@ -191,6 +236,54 @@ impl Service for Jsonrpc {
 
                 Box::new(fut_resp)
             }
+            (&Method::GET, "/healthz") => {
+                info!("{}", access_log);
+                let resp = Response::default().with_headers(http_headers);
+
+                Box::new(future::ok(resp))
+            }
+            (&Method::GET, "/readyz") => {
+                info!("{}", access_log);
+                let fut_resp = future::result(
+                    serde_json::from_str::<JsonrpcRequest>(READINESS_PROBE)
+                        .map_err(ServiceError::JsonrpcSerdeError),
+                )
+                .and_then(FutExtractor::<MQRequest>::extract_from)
+                .and_then({
+                    let headers = http_headers.clone();
+
+                    move |mq_req| {
+                        let timeout_responses = Arc::clone(&responses);
+                        let pulibsher =
+                            Publisher::new(responses, sender, headers, batch_concurrency);
+                        let pulibsher =
+                            TimeoutPublisher::new(pulibsher, timeout, timeout_responses);
+
+                        pulibsher.publish(mq_req)
+                    }
+                })
+                .and_then(|resp| {
+                    resp.into_body()
+                        .concat2()
+                        .map_err(ServiceError::BodyConcatError)
+                })
+                .then(move |outputs| {
+                    let readiness = match outputs {
+                        Ok(outputs) => Readiness::from_probe(
+                            &outputs,
+                            AsMillis::as_millis(&unix_now()),
+                            ready_block_intervals,
+                        ),
+                        Err(err) => Readiness::unavailable(format!("{:?}", err)),
+                    };
+                    if !readiness.ready {
+                        warn!("not ready: {:?}", readiness);
+                    }
+                    Ok(readiness.into_response(http_headers))
+                });
+
+                Box::new(fut_resp)
+            }
             (&Method::OPTIONS, "/") => {
                 info!("{}", access_log);
                 let resp = Response::default().with_headers(handle_preflighted(http_headers));
@@ -233,12 +326,17 @@ pub struct Server {
 }
 
 impl Server {
+    #[allow(unknown_lints, clippy::too_many_arguments)]
     pub fn create(
         addr: &SocketAddr,
         tx: mpsc::Sender<(String, ProtoRequest)>,
         responses: RpcMap,
         timeout: u64,
+        batch_concurrency: usize,
+        max_batch_size: usize,
+        ready_block_intervals: u64,
         allow_origin: &Option<String>,
+        rate_limiter: Option<Arc<RateLimiter>>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let listener = listener_from_socket_addr(&addr)?;
         let addr = listener.local_addr()?;
@@ -255,7 +353,11 @@ impl Server {
                 tx: Mutex::new(tx),
                 responses,
                 timeout,
+                batch_concurrency,
+                max_batch_size,
+                ready_block_intervals,
                 http_headers,
+                rate_limiter,
             }),
         };
 
@@ -308,6 +410,9 @@ mod integration_test {
     use serde_json;
     use tokio_core::reactor::Core;
 
+    use crate::config::{
+        DEFAULT_BATCH_CONCURRENCY, DEFAULT_MAX_BATCH_SIZE, DEFAULT_READY_BLOCK_INTERVALS,
+    };
     use helper::TransferType;
 
     struct Serve {
@@ -337,7 +442,18 @@ mod integration_test {
             .name(format!("test-server-{}", Uuid::new_v4()))
             .spawn(move || {
                 let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
-                let server = Server::create(&addr, tx, responses, timeout, &allow_origin).unwrap();
+                let server = Server::create(
+                    &addr,
+                    tx,
+                    responses,
+                    timeout,
+                    DEFAULT_BATCH_CONCURRENCY,
+                    DEFAULT_MAX_BATCH_SIZE,
+                    DEFAULT_READY_BLOCK_INTERVALS,
+                    &allow_origin,
+                    None,
+                )
+                .unwrap();
 
                 let addr = server.local_addr();
                 addr_tx.send((addr, shutdown_tx)).unwrap();
@@ -557,11 +673,37 @@ mod integration_test {
                 })
         });
 
+        let healthz_uri = hyper::Uri::from_str(
+            format!("http://{}:{}/healthz", serve.addr.ip(), serve.addr.port()).as_str(),
+        )
+        .unwrap();
+        let work_healthz = client.get(healthz_uri).and_then(|resp| {
+            assert_eq!(resp.status().as_u16(), 200);
+            Ok(())
+        });
+
+        // The mock answers the probe without a block.
+        let readyz_uri = hyper::Uri::from_str(
+            format!("http://{}:{}/readyz", serve.addr.ip(), serve.addr.port()).as_str(),
+        )
+        .unwrap();
+        let work_readyz = client.get(readyz_uri).and_then(|resp| {
+            assert_eq!(resp.status().as_u16(), 503);
+            resp.into_body().concat2().and_then(|body| {
+                let rv: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(rv["ready"], false);
+                assert!(rv["reason"].is_string());
+                Ok(())
+            })
+        });
+
         works.push(Box::new(work_empty));
         works.push(Box::new(work_options));
         works.push(Box::new(work_method_not_found));
         works.push(Box::new(work_peercount));
         works.push(Box::new(work_peercount_batch));
+        works.push(Box::new(work_healthz));
+        works.push(Box::new(work_readyz));
 
         let mut core = Core::new().unwrap();
         core.run(futures::future::join_all(works)).unwrap();
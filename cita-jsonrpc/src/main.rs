@@ -68,6 +68,7 @@ extern crate libc;
 extern crate libproto;
 #[macro_use]
 extern crate logger;
+extern crate lru_cache;
 extern crate net2;
 extern crate num_cpus;
 extern crate pubsub;
@@ -97,12 +98,17 @@ mod http_header;
 mod http_server;
 mod mq_handler;
 mod mq_publisher;
+mod rate_limiter;
+mod readiness;
 mod response;
 mod service_error;
 mod ws_handler;
 
 use clap::App;
-use config::{NewTxFlowConfig, ProfileConfig};
+use config::{
+    NewTxFlowConfig, ProfileConfig, DEFAULT_BATCH_CONCURRENCY, DEFAULT_MAX_BATCH_SIZE,
+    DEFAULT_READY_BLOCK_INTERVALS,
+};
 use cpuprofiler::PROFILER;
 use fdlimit::set_fd_limit;
 use futures::Future;
@@ -112,6 +118,7 @@ use libproto::router::{MsgType, RoutingKey, SubModules};
 use libproto::Message;
 use libproto::TryInto;
 use pubsub::start_pubsub;
+use rate_limiter::RateLimiter;
 use std::collections::HashMap;
 use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
@@ -177,6 +184,12 @@ fn main() {
     let ws_responses = Arc::clone(&responses);
     let mut mq_handle = mq_handler::MqHandler::new(responses);
 
+    let rate_limiter = config
+        .rate_limit_config
+        .clone()
+        .filter(|rate_limit_config| rate_limit_config.enable)
+        .map(|rate_limit_config| Arc::new(RateLimiter::new(rate_limit_config)));
+
     //dispatch
     let tx_flow_config = config.new_tx_flow_config;
     thread::spawn(move || {
@@ -206,11 +219,12 @@ fn main() {
     if config.ws_config.enable {
         let ws_config = config.ws_config.clone();
         let tx = tx_relay.clone();
+        let rate_limiter = rate_limiter.clone();
         thread::spawn(move || {
             let url =
                 ws_config.listen_ip.clone() + ":" + &ws_config.listen_port.clone().to_string();
             //let factory = WsFactory::new(ws_responses, tx_pub, 0);
-            let factory = WsFactory::new(ws_responses, tx, 0);
+            let factory = WsFactory::new(ws_responses, tx, 0, rate_limiter);
             info!("WebSocket Listening on {}", url);
             let mut ws_build = ws::Builder::new();
             ws_build.with_settings(ws_config.into());
@@ -232,13 +246,31 @@ fn main() {
 
         let addr = addr.parse().unwrap();
         let timeout = http_config.timeout;
+        let batch_concurrency = http_config
+            .batch_concurrency
+            .unwrap_or(DEFAULT_BATCH_CONCURRENCY);
+        let max_batch_size = http_config
+            .max_batch_size
+            .unwrap_or(DEFAULT_MAX_BATCH_SIZE);
+        let ready_block_intervals = http_config
+            .ready_block_intervals
+            .unwrap_or(DEFAULT_READY_BLOCK_INTERVALS);
         let allow_origin = http_config.allow_origin;
         let _ = thread::Builder::new()
             .name(String::from("http worker"))
             .spawn(move || {
-                let server =
-                    Server::create(&addr, tx_relay, http_responses, timeout, &allow_origin)
-                        .unwrap();
+                let server = Server::create(
+                    &addr,
+                    tx_relay,
+                    http_responses,
+                    timeout,
+                    batch_concurrency,
+                    max_batch_size,
+                    ready_block_intervals,
+                    &allow_origin,
+                    rate_limiter,
+                )
+                .unwrap();
                 let jsonrpc_server = server
                     .jsonrpc()
                     .map_err(|err| eprintln!("server err {}", err));
@@ -18,7 +18,7 @@
 use std::sync::mpsc;
 use std::time::Duration;
 
-use futures::{future::Future, stream::FuturesOrdered, sync::oneshot};
+use futures::{future::Future, stream, sync::oneshot};
 use hyper::HeaderMap as Headers;
 use jsonrpc_types::{
     request::Request as JsonRequest, response::Output as JsonrpcResponse, rpctypes::Id as JsonrpcId,
@@ -66,6 +66,16 @@ impl MQRequest {
             },
         }
     }
+
+    pub fn methods(&self) -> Vec<&str> {
+        match self {
+            MQRequest::Single(ref hybrid_req) => vec![hybrid_req.json_req.get_method()],
+            MQRequest::Batch(ref hybrid_reqs) => hybrid_reqs
+                .iter()
+                .map(|hybrid_req| hybrid_req.json_req.get_method())
+                .collect(),
+        }
+    }
 }
 
 pub type ProtoReqSender = mpsc::Sender<(String, ProtoRequest)>;
@@ -74,58 +84,71 @@ pub struct Publisher {
     responses: RpcMap,
     sender: ProtoReqSender,
     headers: Headers,
+    batch_concurrency: usize,
 }
 
 impl Publisher {
-    pub fn new(responses: RpcMap, sender: ProtoReqSender, headers: Headers) -> Self {
+    pub fn new(
+        responses: RpcMap,
+        sender: ProtoReqSender,
+        headers: Headers,
+        batch_concurrency: usize,
+    ) -> Self {
         Self {
             responses,
             sender,
             headers,
+            batch_concurrency,
         }
     }
 
     pub fn publish(&mut self, req: MQRequest) -> PublishFutResponse {
         use futures::Stream;
-        use std::iter::FromIterator;
+        use std::sync::Arc;
 
         match req {
             MQRequest::Single(req) => {
-                let rx = self.send_request(*req);
+                let rx = send_request(&self.responses, &self.sender, *req);
 
                 let resp = SingleFutureResponse::new(rx, self.headers.clone());
                 PublishFutResponse::Single(resp)
             }
             MQRequest::Batch(reqs) => {
-                let rxs = reqs
-                    .into_iter()
-                    .map(|req| self.send_request(req))
-                    .collect::<Vec<oneshot::Receiver<JsonrpcResponse>>>();
-
-                let resp = BatchFutureResponse::new(
-                    FuturesOrdered::from_iter(rxs).collect(),
-                    self.headers.clone(),
-                );
+                let responses = Arc::clone(&self.responses);
+                let sender = self.sender.clone();
+
+                // Requests are published lazily, so no more than `batch_concurrency`
+                // of them are waiting for a response at the same time.
+                let output = stream::iter_ok(reqs)
+                    .map(move |req| send_request(&responses, &sender, req))
+                    .buffered(self.batch_concurrency.max(1))
+                    .collect();
+
+                let resp = BatchFutureResponse::new(Box::new(output), self.headers.clone());
                 PublishFutResponse::Batch(resp)
             }
         }
     }
+}
 
-    fn send_request(&mut self, hybrid_req: HybridRequest) -> oneshot::Receiver<JsonrpcResponse> {
-        let (json_req, proto_req) = (hybrid_req.json_req, hybrid_req.proto_req);
-        let (tx, rx) = oneshot::channel();
-        let topic = select_topic(json_req.get_method());
+fn send_request(
+    responses: &RpcMap,
+    sender: &ProtoReqSender,
+    hybrid_req: HybridRequest,
+) -> oneshot::Receiver<JsonrpcResponse> {
+    let (json_req, proto_req) = (hybrid_req.json_req, hybrid_req.proto_req);
+    let (tx, rx) = oneshot::channel();
+    let topic = select_topic(json_req.get_method());
 
-        self.responses.lock().insert(
-            proto_req.request_id.clone(),
-            TransferType::HTTP((json_req.get_info(), tx)),
-        );
+    responses.lock().insert(
+        proto_req.request_id.clone(),
+        TransferType::HTTP((json_req.get_info(), tx)),
+    );
 
-        // NOTE: send failure is handled as timeout error
-        let _ = self.sender.send((topic, proto_req));
+    // NOTE: send failure is handled as timeout error
+    let _ = sender.send((topic, proto_req));
 
-        rx
-    }
+    rx
 }
 
 pub struct TimeoutPublisher {
@@ -143,6 +166,20 @@ impl TimeoutPublisher {
         }
     }
 
+    // Calls of a batch are published `batch_concurrency` at a time, and every
+    // window of calls gets the whole timeout. Batches over `max_batch_size`
+    // are rejected before publishing, which bounds the deadline.
+    fn deadline(&self, req: &MQRequest) -> Duration {
+        match req {
+            MQRequest::Single(_) => self.timeout,
+            MQRequest::Batch(ref hybrid_reqs) => {
+                let concurrency = self.publisher.batch_concurrency.max(1);
+                let windows = (hybrid_reqs.len() + concurrency - 1) / concurrency;
+                self.timeout * windows.max(1) as u32
+            }
+        }
+    }
+
     pub fn publish(
         mut self,
         req: MQRequest,
@@ -150,7 +187,7 @@ impl TimeoutPublisher {
         use futures::future::Either;
         use std::sync::Arc;
 
-        let timeout = Delay::new(clock::now() + self.timeout);
+        let timeout = Delay::new(clock::now() + self.deadline(&req));
         let timeout_responses = Arc::clone(&self.timeout_responses);
         let (req_info, req_ids) = match req {
             MQRequest::Single(ref hybrid_req) => (
@@ -188,3 +225,94 @@ impl TimeoutPublisher {
         Box::new(fut_resp)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use futures::Stream;
+    use jsonrpc_proto::response::OutputExt;
+    use jsonrpc_types::{request::RpcRequest as JsonrpcRequest, response::Output};
+    use libproto::protos;
+    use serde_json;
+    use util::Mutex;
+
+    use crate::extractor::FutExtractor;
+
+    // Answer the calls which arrive together in reverse order, and return
+    // the most calls seen waiting for a response at once.
+    fn spawn_responder(
+        responses: RpcMap,
+        rx: mpsc::Receiver<(String, ProtoRequest)>,
+        delay: Duration,
+    ) -> thread::JoinHandle<usize> {
+        thread::spawn(move || {
+            let mut max_waiting = 0;
+            while let Ok((_topic, req)) = rx.recv() {
+                thread::sleep(delay);
+                let mut reqs = vec![req];
+                reqs.extend(rx.try_iter().map(|(_topic, req)| req));
+                max_waiting = max_waiting.max(responses.lock().len());
+
+                for req in reqs.into_iter().rev() {
+                    let value = { responses.lock().remove(&req.request_id) };
+                    if let Some(TransferType::HTTP((req_info, sender))) = value {
+                        let mut content = protos::response::Response::new();
+                        content.set_request_id(req.request_id);
+                        content.set_code(0);
+                        content.set_tx_state(format!("{}", json!({"status": "OK"})));
+                        let _ = sender.send(Output::from_res_info(content, req_info));
+                    }
+                }
+            }
+            max_waiting
+        })
+    }
+
+    #[test]
+    fn test_batch_larger_than_concurrency() {
+        let (batch_size, batch_concurrency) = (5, 2);
+        let reqs: Vec<serde_json::Value> = (0..batch_size)
+            .map(|id| json!({"jsonrpc":"2.0","method":"peerCount","params":[],"id":id}))
+            .collect();
+        let req = serde_json::from_value::<JsonrpcRequest>(json!(reqs)).unwrap();
+        let mq_req = FutExtractor::<MQRequest>::extract_from(req).wait().unwrap();
+
+        let responses: RpcMap = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, rx) = mpsc::channel();
+        // Three windows of calls take longer than one timeout.
+        let responder = spawn_responder(Arc::clone(&responses), rx, Duration::from_millis(150));
+
+        let publisher = Publisher::new(
+            Arc::clone(&responses),
+            tx,
+            Headers::new(),
+            batch_concurrency,
+        );
+        let publisher = TimeoutPublisher::new(
+            publisher,
+            Duration::from_millis(250),
+            Arc::clone(&responses),
+        );
+        let fut_body = publisher.publish(mq_req).and_then(|resp| {
+            resp.into_body()
+                .concat2()
+                .map_err(|_| ServiceError::InternalServerError)
+        });
+        let body = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(fut_body)
+            .unwrap();
+
+        let outputs: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let ids: Vec<u64> = outputs
+            .iter()
+            .map(|output| output["id"].as_u64().unwrap())
+            .collect();
+        assert_eq!(ids, (0..batch_size).collect::<Vec<u64>>());
+        assert_eq!(responder.join().unwrap(), batch_concurrency);
+    }
+}
@@ -0,0 +1,306 @@
+// CITA
+// Copyright 2016-2018 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use lru_cache::LruCache;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+use util::Mutex;
+
+use crate::config::RateLimitConfig;
+
+/// Forget the least recently seen client once this many are tracked.
+const MAX_TRACKED_CLIENTS: usize = 65_536;
+
+/// JSON-RPC error code of a rate limited call, "limit exceeded" in EIP-1474.
+pub const RATE_LIMITED_ERROR_CODE: i64 = -32_005;
+
+/// Why a call is refused.
+#[derive(Debug, PartialEq)]
+pub enum RateLimitError {
+    /// The budget is exhausted for now, retry later.
+    Exhausted,
+    /// The call costs more than a budget can ever hold, it never passes.
+    TooLarge,
+}
+
+impl RateLimitError {
+    pub fn message(&self) -> &'static str {
+        match *self {
+            RateLimitError::Exhausted => "Too many requests, please slow down.",
+            RateLimitError::TooLarge => "Request exceeds the rate limit, please split it.",
+        }
+    }
+}
+
+/// A token bucket refilled continuously at `rate` tokens per second.
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(capacity: f64, now: Instant) -> Self {
+        Bucket {
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Refill the bucket and check if `cost` tokens are available.
+    fn refill_and_check(&mut self, rate: f64, capacity: f64, cost: f64, now: Instant) -> bool {
+        if now > self.last_refill {
+            let elapsed = now.duration_since(self.last_refill);
+            let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+            self.tokens = (self.tokens + secs * rate).min(capacity);
+            self.last_refill = now;
+        }
+        self.tokens >= cost
+    }
+}
+
+/// Enforce per-IP and per-method request budgets.
+///
+/// Every client IP owns a bucket of `ip_burst` tokens refilled at `ip_rate`
+/// tokens per second, a call costs the weight of its method (1 if not
+/// configured). Methods listed in `method_rates` additionally share one
+/// bucket of `rate` tokens across all clients. A forgotten client starts
+/// again with a full bucket.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    clients: Mutex<LruCache<IpAddr, Bucket>>,
+    methods: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self::with_max_clients(config, MAX_TRACKED_CLIENTS)
+    }
+
+    fn with_max_clients(config: RateLimitConfig, max_clients: usize) -> Self {
+        RateLimiter {
+            config,
+            clients: Mutex::new(LruCache::new(max_clients)),
+            methods: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Cost of one call of `method`.
+    pub fn weight(&self, method: &str) -> u32 {
+        self.config.method_weights.get(method).cloned().unwrap_or(1)
+    }
+
+    /// Seconds a refused client should wait before calling `methods` again,
+    /// the time `ip_rate` takes to refill their cost.
+    pub fn retry_after(&self, methods: &[&str]) -> u64 {
+        let cost: u64 = methods
+            .iter()
+            .map(|method| u64::from(self.weight(method)))
+            .sum();
+        let rate = u64::from(self.config.ip_rate.max(1));
+        ((cost + rate - 1) / rate).max(1)
+    }
+
+    /// Take the budget for calling `methods` from `ip`.
+    ///
+    /// Takes nothing if the calls are refused.
+    pub fn check(&self, ip: IpAddr, methods: &[&str]) -> Result<(), RateLimitError> {
+        self.check_at(ip, methods, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, methods: &[&str], now: Instant) -> Result<(), RateLimitError> {
+        let mut calls: HashMap<&str, u32> = HashMap::new();
+        for method in methods {
+            *calls.entry(*method).or_insert(0) += 1;
+        }
+
+        // Buckets never hold more than their capacity.
+        let cost: u32 = methods.iter().map(|method| self.weight(method)).sum();
+        if cost > self.config.ip_burst {
+            return Err(RateLimitError::TooLarge);
+        }
+        for (method, count) in &calls {
+            match self.config.method_rates.get(*method) {
+                Some(rate) if count > rate => return Err(RateLimitError::TooLarge),
+                _ => {}
+            }
+        }
+
+        let mut method_buckets = self.methods.lock();
+        for (method, count) in &calls {
+            if let Some(rate) = self.config.method_rates.get(*method) {
+                let rate = f64::from(*rate);
+                let bucket = method_buckets
+                    .entry((*method).to_owned())
+                    .or_insert_with(|| Bucket::new(rate, now));
+                if !bucket.refill_and_check(rate, rate, f64::from(*count), now) {
+                    return Err(RateLimitError::Exhausted);
+                }
+            }
+        }
+
+        let rate = f64::from(self.config.ip_rate);
+        let capacity = f64::from(self.config.ip_burst);
+        let mut client_buckets = self.clients.lock();
+        if !client_buckets.contains_key(&ip) {
+            client_buckets.insert(ip, Bucket::new(capacity, now));
+        }
+        let client = client_buckets
+            .get_mut(&ip)
+            .expect("client bucket inserted above");
+        if !client.refill_and_check(rate, capacity, f64::from(cost), now) {
+            return Err(RateLimitError::Exhausted);
+        }
+
+        client.tokens -= f64::from(cost);
+        for (method, count) in &calls {
+            if let Some(bucket) = method_buckets.get_mut(*method) {
+                bucket.tokens -= f64::from(*count);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimitError, RateLimiter};
+    use crate::config::RateLimitConfig;
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            enable: true,
+            ip_rate: 2,
+            ip_burst: 4,
+            method_weights: vec![("getLogs".to_owned(), 3)].into_iter().collect(),
+            method_rates: vec![("sendRawTransaction".to_owned(), 1)]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_ip_budget() {
+        let limiter = RateLimiter::new(config());
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let other: IpAddr = "127.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limiter
+            .check_at(ip, &["blockNumber", "blockNumber"], now)
+            .is_ok());
+        assert!(limiter.check_at(ip, &["blockNumber"], now).is_ok());
+        // Only 1 token left, getLogs costs 3.
+        assert_eq!(
+            limiter.check_at(ip, &["getLogs"], now),
+            Err(RateLimitError::Exhausted)
+        );
+        assert!(limiter.check_at(other, &["getLogs"], now).is_ok());
+        // 1 second refills 2 tokens.
+        assert!(limiter
+            .check_at(ip, &["getLogs"], now + Duration::from_secs(1))
+            .is_ok());
+        assert_eq!(
+            limiter.check_at(ip, &["blockNumber"], now + Duration::from_secs(1)),
+            Err(RateLimitError::Exhausted)
+        );
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let limiter = RateLimiter::new(config());
+        assert_eq!(limiter.retry_after(&["blockNumber"]), 1);
+        assert_eq!(limiter.retry_after(&["getLogs"]), 2);
+        assert_eq!(limiter.retry_after(&["getLogs", "getLogs"]), 3);
+    }
+
+    #[test]
+    fn test_method_budget() {
+        let limiter = RateLimiter::new(config());
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let other: IpAddr = "127.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.check_at(ip, &["sendRawTransaction"], now).is_ok());
+        assert_eq!(
+            limiter.check_at(other, &["sendRawTransaction"], now),
+            Err(RateLimitError::Exhausted)
+        );
+        // A rejected batch takes nothing from the client budget.
+        assert_eq!(
+            limiter.check_at(ip, &["blockNumber", "sendRawTransaction"], now),
+            Err(RateLimitError::Exhausted)
+        );
+        assert!(limiter
+            .check_at(ip, &["blockNumber", "blockNumber", "blockNumber"], now)
+            .is_ok());
+        assert!(limiter
+            .check_at(other, &["sendRawTransaction"], now + Duration::from_secs(1))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_too_large() {
+        let limiter = RateLimiter::new(config());
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        // Costs 6 with a burst of 4.
+        assert_eq!(
+            limiter.check_at(ip, &["getLogs", "getLogs"], now),
+            Err(RateLimitError::TooLarge)
+        );
+        // Two calls with a method rate of 1.
+        assert_eq!(
+            limiter.check_at(ip, &["sendRawTransaction", "sendRawTransaction"], now),
+            Err(RateLimitError::TooLarge)
+        );
+        // Refused calls take nothing.
+        assert!(limiter
+            .check_at(ip, &["blockNumber", "sendRawTransaction", "getLogs"], now)
+            .is_err());
+        assert!(limiter
+            .check_at(ip, &["getLogs", "sendRawTransaction"], now)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_client_eviction() {
+        let limiter = RateLimiter::with_max_clients(config(), 2);
+        let ips: Vec<IpAddr> = (1..4)
+            .map(|i| format!("127.0.0.{}", i).parse().unwrap())
+            .collect();
+        let now = Instant::now();
+
+        assert!(limiter.check_at(ips[0], &["getLogs"], now).is_ok());
+        assert!(limiter.check_at(ips[1], &["getLogs"], now).is_ok());
+        // Seeing the first client again keeps it, the second is forgotten.
+        assert_eq!(
+            limiter.check_at(ips[0], &["getLogs"], now),
+            Err(RateLimitError::Exhausted)
+        );
+        assert!(limiter.check_at(ips[2], &["getLogs"], now).is_ok());
+        assert_eq!(limiter.clients.lock().len(), 2);
+        assert_eq!(
+            limiter.check_at(ips[0], &["getLogs"], now),
+            Err(RateLimitError::Exhausted)
+        );
+        assert!(limiter.check_at(ips[1], &["getLogs"], now).is_ok());
+    }
+}
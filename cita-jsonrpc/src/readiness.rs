@@ -0,0 +1,191 @@
+// CITA
+// Copyright 2016-2018 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use hyper::{Body, HeaderMap as Headers, Response, StatusCode};
+use serde_json::{self, Value};
+
+use crate::response::{HyperResponseExt, IntoResponse};
+
+/// Fetch the latest block and the block interval in one batch.
+pub const READINESS_PROBE: &str = r#"[
+    {"jsonrpc":"2.0","method":"getBlockByNumber","params":["latest",false],"id":1},
+    {"jsonrpc":"2.0","method":"getMetaData","params":["latest"],"id":2}
+]"#;
+
+/// Readiness of the node, the body of `GET /readyz`.
+///
+/// The node is ready while its latest block is at most `ready_block_intervals`
+/// block intervals old.
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    pub ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+    /// Milliseconds since the latest block.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_age: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_block_age: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Readiness {
+    pub fn unavailable(reason: String) -> Self {
+        Readiness {
+            reason: Some(reason),
+            ..Default::default()
+        }
+    }
+
+    /// Evaluate the outputs of `READINESS_PROBE` at `now` (in milliseconds).
+    pub fn from_probe(outputs: &[u8], now: u64, ready_block_intervals: u64) -> Self {
+        let outputs: Vec<Value> = match serde_json::from_slice(outputs) {
+            Ok(outputs) => outputs,
+            Err(err) => return Readiness::unavailable(format!("invalid probe outputs: {}", err)),
+        };
+        let result = |id: u64, method: &str| -> Result<&Value, String> {
+            let output = outputs
+                .iter()
+                .find(|output| output["id"] == id)
+                .ok_or_else(|| format!("{} not answered", method))?;
+            if !output["error"].is_null() {
+                return Err(format!("{} failed: {}", method, output["error"]));
+            }
+            match output["result"] {
+                Value::Null => Err(format!("{} returned nothing", method)),
+                ref result => Ok(result),
+            }
+        };
+        let evaluate = || -> Result<Self, String> {
+            let header = &result(1, "getBlockByNumber")?["header"];
+            let height = header["number"]
+                .as_str()
+                .and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+                .ok_or_else(|| "latest block has no number".to_owned())?;
+            let timestamp = header["timestamp"]
+                .as_u64()
+                .ok_or_else(|| "latest block has no timestamp".to_owned())?;
+            let block_interval = result(2, "getMetaData")?["blockInterval"]
+                .as_u64()
+                .ok_or_else(|| "metadata has no blockInterval".to_owned())?;
+
+            let block_age = now.saturating_sub(timestamp);
+            let max_block_age = block_interval * ready_block_intervals;
+            let ready = block_age <= max_block_age;
+            Ok(Readiness {
+                ready,
+                height: Some(height),
+                block_age: Some(block_age),
+                max_block_age: Some(max_block_age),
+                reason: if ready {
+                    None
+                } else {
+                    Some("latest block is too old".to_owned())
+                },
+            })
+        };
+        evaluate().unwrap_or_else(Readiness::unavailable)
+    }
+}
+
+impl IntoResponse for Readiness {
+    fn into_response(self, http_headers: Headers) -> Response<Body> {
+        let status = if self.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        let body = serde_json::to_vec(&self).unwrap_or_else(|e| {
+            error!("serde_json: {}", e);
+            Vec::new()
+        });
+
+        Response::default()
+            .with_headers(http_headers)
+            .with_status(status)
+            .with_body(Body::from(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Readiness;
+
+    const BLOCK_TIME: u64 = 1_541_058_686_340;
+
+    fn outputs(block: &str, meta: &str) -> Vec<u8> {
+        format!(
+            r#"[{{"jsonrpc":"2.0","id":1,{}}},{{"jsonrpc":"2.0","id":2,{}}}]"#,
+            block, meta
+        )
+        .into_bytes()
+    }
+
+    fn block_and_meta() -> Vec<u8> {
+        outputs(
+            &format!(
+                r#""result":{{"header":{{"number":"0x1a","timestamp":{}}}}}"#,
+                BLOCK_TIME
+            ),
+            r#""result":{"blockInterval":3000}"#,
+        )
+    }
+
+    #[test]
+    fn test_recent_block() {
+        let readiness = Readiness::from_probe(&block_and_meta(), BLOCK_TIME + 30_000, 10);
+        assert_eq!(
+            readiness,
+            Readiness {
+                ready: true,
+                height: Some(26),
+                block_age: Some(30_000),
+                max_block_age: Some(30_000),
+                reason: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_stale_block() {
+        let readiness = Readiness::from_probe(&block_and_meta(), BLOCK_TIME + 30_001, 10);
+        assert!(!readiness.ready);
+        assert_eq!(readiness.height, Some(26));
+        assert_eq!(readiness.block_age, Some(30_001));
+    }
+
+    #[test]
+    fn test_failed_probe() {
+        let error = r#""error":{"code":-32099,"message":"System timeout"}"#;
+        let readiness = Readiness::from_probe(
+            &outputs(error, r#""result":{"blockInterval":3000}"#),
+            BLOCK_TIME,
+            10,
+        );
+        assert!(!readiness.ready);
+        assert_eq!(readiness.height, None);
+        assert!(readiness
+            .reason
+            .unwrap()
+            .starts_with("getBlockByNumber failed"));
+
+        let readiness = Readiness::from_probe(b"{}", BLOCK_TIME, 10);
+        assert!(!readiness.ready);
+    }
+}
@@ -15,7 +15,6 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use futures::stream::{Collect, FuturesOrdered};
 use futures::{future::Future, sync::oneshot, Async, Poll};
 use hyper::{HeaderMap as Headers, Response as HyperResponse, StatusCode};
 use jsonrpc_types::response::Output;
@@ -131,7 +130,7 @@ impl Future for SingleFutureResponse {
     }
 }
 
-type BatchOutput = Collect<FuturesOrdered<oneshot::Receiver<Output>>>;
+type BatchOutput = Box<dyn Future<Item = Vec<Output>, Error = oneshot::Canceled> + Send>;
 
 pub struct BatchFutureResponse {
     output: BatchOutput,
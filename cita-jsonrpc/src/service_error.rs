@@ -15,14 +15,19 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use hyper::header::{HeaderValue, RETRY_AFTER};
 use hyper::{Body, HeaderMap as Headers, Response, StatusCode};
 use jsonrpc_types::{request::RequestInfo, response::RpcFailure};
 use serde_json;
 
+use crate::rate_limiter::{RateLimitError, RATE_LIMITED_ERROR_CODE};
 use crate::response::{HyperResponseExt, IntoResponse};
 
 const MSG_TIMEOUT_RESEND: &str = r#"{"err": "System timeout, please resend."}"#;
 const MSG_INCOMPLETE_REQUEST: &str = r#"{"err": "Incomplete request, please resend."}"#;
+const MSG_REQUEST_TOO_LARGE: &str =
+    r#"{"err": "Request exceeds the rate limit, please split it."}"#;
+const MSG_BATCH_TOO_LARGE: &str = "Too many calls in a batch, please split it.";
 
 #[derive(Debug)]
 pub enum ServiceError {
@@ -31,6 +36,11 @@ pub enum ServiceError {
     JsonrpcPartCompleteError(RequestInfo, jsonrpc_types::Error),
     MQRpcTimeout(Option<RequestInfo>),
     MQResponsePollIncompleteError,
+    /// Carries the seconds to wait before retrying.
+    RateLimited(u64),
+    RequestTooLarge,
+    /// The batch has more calls than `max_batch_size`.
+    BatchTooLarge,
     InternalServerError,
 }
 
@@ -75,9 +85,70 @@ impl IntoResponse for ServiceError {
 
                 new_response(None, Some(Body::from(resp_body)))
             }
+            ServiceError::RateLimited(retry_after) => {
+                let resp_body = format!(r#"{{"err": "{}"}}"#, RateLimitError::Exhausted.message());
+                let mut resp = new_response(
+                    Some(StatusCode::TOO_MANY_REQUESTS),
+                    Some(Body::from(resp_body)),
+                );
+                resp.headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+                resp
+            }
+            ServiceError::BatchTooLarge => {
+                let err = jsonrpc_types::Error::server_error(
+                    RATE_LIMITED_ERROR_CODE,
+                    MSG_BATCH_TOO_LARGE,
+                );
+                let resp_body = serde_json::to_vec(&RpcFailure::from(err)).unwrap_or_else(|e| {
+                    error!("serde_json: {}", e);
+                    MSG_BATCH_TOO_LARGE.as_bytes().to_vec()
+                });
+
+                new_response(None, Some(Body::from(resp_body)))
+            }
+            ServiceError::RequestTooLarge => new_response(
+                Some(StatusCode::PAYLOAD_TOO_LARGE),
+                Some(Body::from(MSG_REQUEST_TOO_LARGE)),
+            ),
             ServiceError::InternalServerError | ServiceError::MQResponsePollIncompleteError => {
                 new_response(Some(StatusCode::INTERNAL_SERVER_ERROR), None)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ServiceError;
+    use crate::response::IntoResponse;
+    use futures::{Future, Stream};
+    use hyper::header::RETRY_AFTER;
+    use hyper::{HeaderMap, StatusCode};
+    use serde_json;
+
+    #[test]
+    fn test_rate_limited_response() {
+        let resp = ServiceError::RateLimited(2).into_response(HeaderMap::new());
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()[RETRY_AFTER], "2");
+        let body = resp.into_body().concat2().wait().unwrap();
+        assert_eq!(
+            &body[..],
+            &br#"{"err": "Too many requests, please slow down."}"#[..]
+        );
+    }
+
+    #[test]
+    fn test_batch_too_large_response() {
+        let resp = ServiceError::BatchTooLarge.into_response(HeaderMap::new());
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().concat2().wait().unwrap();
+        let failure: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(failure["error"]["code"], -32_005);
+        assert_eq!(
+            failure["error"]["message"],
+            "Too many calls in a batch, please split it."
+        );
+    }
+}
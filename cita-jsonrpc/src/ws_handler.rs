@@ -22,16 +22,19 @@ use jsonrpc_types::response::RpcFailure;
 use jsonrpc_types::Error;
 use libproto::request::Request as ProtoRequest;
 use num_cpus;
+use rate_limiter::{RateLimiter, RATE_LIMITED_ERROR_CODE};
 use serde_json;
+use std::net::IpAddr;
 use std::sync::{mpsc, Arc};
 use threadpool::ThreadPool;
-use ws::{self as ws, CloseCode, Factory, Handler};
+use ws::{self as ws, CloseCode, Factory, Handler, Handshake};
 
 pub struct WsFactory {
     //TODO 定时清理工作
     responses: RpcMap,
     thread_pool: ThreadPool,
     tx: mpsc::Sender<(String, ProtoRequest)>,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl WsFactory {
@@ -39,6 +42,7 @@ impl WsFactory {
         responses: RpcMap,
         tx: mpsc::Sender<(String, ProtoRequest)>,
         thread_num: usize,
+        rate_limiter: Option<Arc<RateLimiter>>,
     ) -> WsFactory {
         let thread_number = if thread_num == 0 {
             num_cpus::get()
@@ -50,6 +54,7 @@ impl WsFactory {
             responses,
             thread_pool,
             tx,
+            rate_limiter,
         }
     }
 }
@@ -62,17 +67,26 @@ impl Factory for WsFactory {
             responses: Arc::clone(&self.responses),
             tx: self.tx.clone(),
             thread_pool: self.thread_pool.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            remote_ip: None,
         }
     }
 }
 
 impl Handler for WsHandler {
+    fn on_open(&mut self, shake: Handshake) -> ws::Result<()> {
+        self.remote_ip = shake.peer_addr.map(|addr| addr.ip());
+        Ok(())
+    }
+
     fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
         trace!("Server got message '{}'  post thread_pool deal task ", msg);
         // let this = self.clone();
         let tx = self.tx.clone();
         let response = Arc::clone(&self.responses);
         let sender = self.sender.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let remote_ip = self.remote_ip;
 
         self.thread_pool.execute(move || {
             let mut req_info = RequestInfo::null();
@@ -82,6 +96,16 @@ impl Handler for WsHandler {
                 .and_then(|part_req| {
                     req_info = part_req.get_info();
                     part_req.complete_and_into_proto().map(|(full_req, req)| {
+                        if let (Some(limiter), Some(ip)) = (rate_limiter.as_ref(), remote_ip) {
+                            if let Err(limited) = limiter.check(ip, &[full_req.get_method()]) {
+                                warn!("rate limited request from {}", ip);
+                                let err =
+                                    Error::server_error(RATE_LIMITED_ERROR_CODE, limited.message());
+                                let failure = RpcFailure::from_options(req_info.clone(), err);
+                                let _ = sender.send(serde_json::to_string(&failure).unwrap());
+                                return;
+                            }
+                        }
                         let request_id = req.request_id.clone();
                         let topic = select_topic(&full_req.get_method());
                         let _ = tx.send((topic, req));
@@ -120,4 +144,6 @@ pub struct WsHandler {
     thread_pool: ThreadPool,
     sender: ws::Sender,
     tx: mpsc::Sender<(String, ProtoRequest)>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    remote_ip: Option<IpAddr>,
 }
@@ -17,6 +17,7 @@ from create_init_data import dictlist_to_ordereddict
 
 
 DEFAULT_PREVHASH = '0x{:064x}'.format(0)
+SPEC_VERSION = '1.0'
 BLOCK_GAS_LIMIT = 471238800
 
 
@@ -240,6 +241,7 @@ class GenesisData(object):
         with open(filepath, 'w') as stream:
             json.dump(
                 dict(
+                    spec_version=SPEC_VERSION,
                     timestamp=self.timestamp,
                     prevhash=self.prevhash,
                     alloc=self.accounts,